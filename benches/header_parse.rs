@@ -0,0 +1,72 @@
+//! Throughput of reading responses with representative header blocks.
+//!
+//! Run with `cargo bench --features test-util`. Each response is read from a
+//! [`MockNetwork`], both in a single read and in small reads, which rescan the header received
+//! so far for its end.
+//!
+//! Baseline time per response on an x86_64 Linux machine, before optimizing the header scan:
+//!
+//! | response             | single read | 64 byte reads |
+//! |----------------------|-------------|---------------|
+//! | `small`              | 0.8 µs      | 1.0 µs        |
+//! | `many_headers`       | 5.4 µs      | 38 µs         |
+//! | `large_header_block` | 16 µs       | 396 µs        |
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use reqwless::client::HttpClient;
+use reqwless::mock::{block_on, MockNetwork};
+use reqwless::request::Request;
+use std::fmt::Write;
+
+fn small() -> String {
+    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: 2\r\n\r\n{}".into()
+}
+
+fn many_headers() -> String {
+    let mut response = String::from("HTTP/1.1 200 OK\r\n");
+    for i in 0..50 {
+        write!(response, "X-Header-{}: value-{}\r\n", i, i).unwrap();
+    }
+    response.push_str("Content-Length: 2\r\n\r\n{}");
+    response
+}
+
+fn large_header_block() -> String {
+    let mut response = String::from("HTTP/1.1 200 OK\r\n");
+    for i in 0..4 {
+        write!(response, "Set-Cookie: session{}={}\r\n", i, "a".repeat(1000)).unwrap();
+    }
+    response.push_str("Content-Length: 2\r\n\r\n{}");
+    response
+}
+
+fn read_response(rx: &[u8], read_chunk_size: Option<usize>) {
+    let mut tx = [0; 64];
+    let mut conn = MockNetwork::new(rx, &mut tx);
+    if let Some(chunk_size) = read_chunk_size {
+        conn = conn.with_read_chunk_size(chunk_size);
+    }
+    let mut client = HttpClient::new(&mut conn, "localhost");
+    let mut rx_buf = [0; 8192];
+    let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+    black_box(response.status);
+}
+
+fn header_parse(c: &mut Criterion) {
+    let responses = [
+        ("small", small()),
+        ("many_headers", many_headers()),
+        ("large_header_block", large_header_block()),
+    ];
+    let mut group = c.benchmark_group("read_response");
+    for (name, response) in responses.iter() {
+        group.throughput(Throughput::Bytes(response.len() as u64));
+        group.bench_function(*name, |b| b.iter(|| read_response(response.as_bytes(), None)));
+        group.bench_function(format!("{}/64_byte_reads", name), |b| {
+            b.iter(|| read_response(response.as_bytes(), Some(64)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, header_parse);
+criterion_main!(benches);
@@ -0,0 +1,553 @@
+//! Streaming of request and response bodies.
+use crate::client::{Carryover, Error};
+use crate::Network;
+use core::fmt::Write as _;
+use core::future::Future;
+use embedded_io::asynch::Read;
+use embedded_io::{Error as _, Io};
+use heapless::String;
+
+/// How the length of a request body is conveyed to the server.
+#[derive(Clone, Copy)]
+pub(crate) enum BodyFraming {
+    /// The request has no body.
+    None,
+    /// The body length is announced up front in the `Content-Length` header.
+    ///
+    /// A streamed body may be larger than the address space of small targets.
+    ContentLength(u64),
+    /// The body is sent using chunked transfer encoding.
+    Chunked,
+}
+
+/// Write all of `data` to the connection, in writes of at most `chunk_size` bytes.
+pub(crate) async fn write_all<N: Network>(
+    connection: &mut N,
+    mut data: &[u8],
+    chunk_size: Option<usize>,
+) -> Result<(), Error> {
+    while !data.is_empty() {
+        let len = chunk_size.unwrap_or(data.len()).min(data.len());
+        let n = connection.write(&data[..len]).await.map_err(|e| e.kind())?;
+        if n == 0 {
+            warn!("Connection accepted no data with {} bytes left to write", data.len());
+            return Err(Error::ConnectionClosed);
+        }
+        data = &data[n..];
+    }
+    Ok(())
+}
+
+/// A writer streaming a request body to the connection.
+///
+/// A body of known length is sent as is, and exactly that many bytes must be written before
+/// [`finish`](Self::finish) is called. Otherwise, each write is sent as a chunk using chunked
+/// transfer encoding.
+pub struct BodyWriter<'a, N>
+where
+    N: Network,
+{
+    connection: &'a mut N,
+    chunk_size: Option<usize>,
+    framing: BodyFraming,
+}
+
+impl<'a, N> BodyWriter<'a, N>
+where
+    N: Network,
+{
+    pub(crate) fn new(connection: &'a mut N, chunk_size: Option<usize>, framing: BodyFraming) -> Self {
+        Self {
+            connection,
+            chunk_size,
+            framing,
+        }
+    }
+
+    /// Write a part of the body.
+    ///
+    /// Writing more bytes than the announced body length fails with
+    /// [`Error::BodyLengthMismatch`].
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), Error> {
+        match self.framing {
+            // An empty chunk would terminate the body
+            BodyFraming::Chunked if data.is_empty() => Ok(()),
+            BodyFraming::Chunked => {
+                let mut size: String<16> = String::new();
+                write!(size, "{:x}\r\n", data.len()).map_err(|_| Error::Codec)?;
+                write_all(self.connection, size.as_bytes(), self.chunk_size).await?;
+                write_all(self.connection, data, self.chunk_size).await?;
+                write_all(self.connection, b"\r\n", self.chunk_size).await
+            }
+            BodyFraming::ContentLength(remaining) if data.len() as u64 <= remaining => {
+                write_all(self.connection, data, self.chunk_size).await?;
+                self.framing = BodyFraming::ContentLength(remaining - data.len() as u64);
+                Ok(())
+            }
+            BodyFraming::None if data.is_empty() => Ok(()),
+            _ => {
+                warn!("Body exceeds the announced length");
+                Err(Error::BodyLengthMismatch)
+            }
+        }
+    }
+
+    /// Write the data produced by `source` through `buf`, until `source` signals the end of the
+    /// stream by returning 0 from a read.
+    ///
+    /// Each read is written as soon as it completes, as a chunk when using chunked transfer
+    /// encoding, so data is sent as it becomes available. The body still needs to be completed with
+    /// [`finish`](Self::finish).
+    pub async fn write_from<R: Read>(&mut self, source: &mut R, buf: &mut [u8]) -> Result<(), Error> {
+        loop {
+            let n = source.read(buf).await.map_err(|e| e.kind())?;
+            if n == 0 {
+                return Ok(());
+            }
+            self.write(&buf[..n]).await?;
+        }
+    }
+
+    /// Complete the body.
+    ///
+    /// Fails with [`Error::BodyLengthMismatch`] if fewer bytes than the announced body length were
+    /// written.
+    pub async fn finish(self) -> Result<(), Error> {
+        match self.framing {
+            BodyFraming::Chunked => write_all(self.connection, b"0\r\n\r\n", self.chunk_size).await,
+            BodyFraming::ContentLength(remaining) if remaining > 0 => {
+                warn!("Body is {} bytes short of the announced length", remaining);
+                Err(Error::BodyLengthMismatch)
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A reader streaming a response body from the connection.
+///
+/// Body bytes that were received together with the response header are returned first, after which
+/// `buf` is reused to read the rest of the body from the connection. A chunked body is decoded in
+/// place in `buf`. Reads return 0 at the end of the body, once its announced length has been read,
+/// or when the connection is closed for a body of unknown length.
+///
+/// Data of the next response received after the body is kept by the client for the next request.
+/// If the reader is dropped before the end of the body, the rest of the body is left unread on the
+/// connection, so further requests of the client fail with
+/// [`Error::ConnectionClosed`](crate::client::Error::ConnectionClosed).
+pub struct BodyReader<'a, N>
+where
+    N: Network,
+{
+    connection: &'a mut N,
+    carryover: &'a mut Carryover,
+    buf: &'a mut [u8],
+    pos: usize,
+    len: usize,
+    remaining: Option<u64>,
+    /// The decoder of a chunked body, whose undecoded bytes are kept in `buf[len..raw_end]`.
+    chunked: Option<ChunkedDecoder>,
+    raw_end: usize,
+}
+
+impl<'a, N> BodyReader<'a, N>
+where
+    N: Network,
+{
+    /// Create a reader for a body of `content_length` bytes, of which the first `buffered` bytes
+    /// are already in `buf`. A body of unknown length is read until the connection is closed,
+    /// unless it is decoded with a `chunked` decoder.
+    ///
+    /// Data received after the body is recorded in `carryover`, which marks the connection as out
+    /// of sync until the end of the body is read.
+    pub(crate) fn new(
+        connection: &'a mut N,
+        carryover: &'a mut Carryover,
+        buf: &'a mut [u8],
+        buffered: usize,
+        content_length: Option<u64>,
+        chunked: Option<ChunkedDecoder>,
+    ) -> Self {
+        carryover.unread_body = true;
+        let mut reader = if chunked.is_some() {
+            Self {
+                connection,
+                carryover,
+                buf,
+                pos: 0,
+                len: 0,
+                remaining: None,
+                chunked,
+                raw_end: buffered,
+            }
+        } else {
+            let len = content_length.map_or(buffered, |len| clamp(buffered, len));
+            if buffered > len {
+                carryover.set(&buf[len..buffered]);
+            }
+            Self {
+                connection,
+                carryover,
+                buf,
+                pos: 0,
+                len,
+                remaining: content_length,
+                chunked: None,
+                raw_end: 0,
+            }
+        };
+        reader.check_end();
+        reader
+    }
+
+    /// Mark the connection as ready for the next request once the whole body has been consumed.
+    fn check_end(&mut self) {
+        let end = match &self.chunked {
+            Some(decoder) => decoder.is_done(),
+            None => self.remaining == Some(0),
+        };
+        if end && self.pos == self.len {
+            self.carryover.unread_body = false;
+        }
+    }
+
+    /// Return the next part of the body, reading from the connection if nothing is buffered.
+    ///
+    /// An empty slice is returned at the end of the body.
+    pub async fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        if self.chunked.is_some() {
+            self.fill_buf_chunked().await?;
+            self.check_end();
+            return Ok(&self.buf[self.pos..self.len]);
+        }
+        if self.pos == self.len && self.remaining != Some(0) {
+            let to_read = self.remaining.map_or(self.buf.len(), |len| clamp(self.buf.len(), len));
+            let n = self
+                .connection
+                .read(&mut self.buf[..to_read])
+                .await
+                .map_err(|e| e.kind())?;
+            if let Some(remaining) = self.remaining.filter(|_| n == 0) {
+                warn!("Connection closed with {} bytes of body remaining", remaining);
+                return Err(Error::BodyLengthMismatch);
+            }
+            self.pos = 0;
+            self.len = n;
+        }
+        Ok(&self.buf[self.pos..self.len])
+    }
+
+    async fn fill_buf_chunked(&mut self) -> Result<(), Error> {
+        while self.pos == self.len {
+            // Move the undecoded bytes to the front and decode them in place
+            self.buf.copy_within(self.len..self.raw_end, 0);
+            self.raw_end -= self.len;
+            self.pos = 0;
+            self.len = 0;
+            let decoder = self.chunked.as_mut().unwrap();
+            let (decoded, consumed) = decoder.decode(self.buf, 0, 0, self.raw_end)?;
+            self.buf.copy_within(consumed..self.raw_end, decoded);
+            self.raw_end -= consumed - decoded;
+            self.len = decoded;
+            if decoder.is_done() && self.raw_end > self.len {
+                // The rest belongs to the next response
+                self.carryover.set(&self.buf[self.len..self.raw_end]);
+                self.raw_end = self.len;
+            }
+            if decoded > 0 || decoder.is_done() {
+                break;
+            }
+
+            if self.raw_end == self.buf.len() {
+                warn!("Chunk header does not fit in {} bytes", self.buf.len());
+                return Err(Error::BufferTooSmall);
+            }
+            let n = self
+                .connection
+                .read(&mut self.buf[self.raw_end..])
+                .await
+                .map_err(|e| e.kind())?;
+            if n == 0 {
+                if self.chunked.as_mut().unwrap().finish() {
+                    // Drop the incomplete trailer
+                    self.raw_end = self.len;
+                    break;
+                }
+                warn!("Connection closed before the end of the chunked body");
+                return Err(Error::BodyLengthMismatch);
+            }
+            self.raw_end += n;
+        }
+        Ok(())
+    }
+
+    /// Mark `amt` bytes returned by [`fill_buf`](Self::fill_buf) as consumed.
+    pub fn consume(&mut self, amt: usize) {
+        debug_assert!(self.pos + amt <= self.len);
+        self.pos += amt;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= amt as u64;
+        }
+        self.check_end();
+    }
+
+    /// Read the rest of the body, passing it to `write_page` in pages of `page.len()` bytes.
+    ///
+    /// The body is collected in `page`, so that `write_page` is only called with full pages except
+    /// for the last call, which gets the end of the body if its length is not a multiple of the
+    /// page size. Returns the number of bytes read.
+    pub async fn stream_to_aligned<F>(&mut self, page: &mut [u8], mut write_page: F) -> Result<usize, Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        assert!(!page.is_empty());
+        let mut total = 0;
+        let mut filled = 0;
+        loop {
+            let data = self.fill_buf().await?;
+            if data.is_empty() {
+                break;
+            }
+            let n = core::cmp::min(data.len(), page.len() - filled);
+            page[filled..filled + n].copy_from_slice(&data[..n]);
+            self.consume(n);
+            filled += n;
+            total += n;
+            if filled == page.len() {
+                write_page(page);
+                filled = 0;
+            }
+        }
+        if filled > 0 {
+            write_page(&page[..filled]);
+        }
+        Ok(total)
+    }
+}
+
+impl<'a, N> Io for BodyReader<'a, N>
+where
+    N: Network + 'a,
+{
+    type Error = Error;
+}
+
+impl<'a, N> Read for BodyReader<'a, N>
+where
+    N: Network + 'a,
+{
+    type ReadFuture<'f> = impl Future<Output = Result<usize, Error>> where Self: 'f;
+
+    fn read<'f>(&'f mut self, buf: &'f mut [u8]) -> Self::ReadFuture<'f> {
+        async move {
+            let data = self.fill_buf().await?;
+            let n = core::cmp::min(data.len(), buf.len());
+            buf[..n].copy_from_slice(&data[..n]);
+            self.consume(n);
+            Ok(n)
+        }
+    }
+}
+
+/// Return the smaller of a buffer length and a body length, which fits in a `usize`.
+pub(crate) fn clamp(len: usize, body_len: u64) -> usize {
+    core::cmp::min(len as u64, body_len) as usize
+}
+
+/// A decoder for the chunked transfer encoding, working in place in a buffer.
+pub(crate) struct ChunkedDecoder {
+    state: ChunkState,
+    lenient_end: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ChunkState {
+    /// Expecting a chunk size line.
+    Size,
+    /// Within the data of a chunk, with the given number of bytes left.
+    Data(usize),
+    /// Expecting the line break following the data of a chunk.
+    DataEnd,
+    /// Skipping the trailer following the last chunk.
+    Trailer,
+    /// The terminating empty line has been read.
+    Done,
+}
+
+impl ChunkedDecoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            state: ChunkState::Size,
+            lenient_end: false,
+        }
+    }
+
+    /// Accept a body ending with the last chunk, without the trailer section and line break that
+    /// should follow it.
+    pub(crate) fn with_lenient_end(mut self) -> Self {
+        self.lenient_end = true;
+        self
+    }
+
+    /// Whether the end of the body has been reached.
+    pub(crate) fn is_done(&self) -> bool {
+        self.state == ChunkState::Done
+    }
+
+    /// Signal that the encoded data ended, returning whether the body is complete.
+    ///
+    /// If the decoder is lenient, a body is complete once its last chunk is read, and the
+    /// undecoded part of the trailer must be dropped.
+    pub(crate) fn finish(&mut self) -> bool {
+        if self.lenient_end && self.state == ChunkState::Trailer {
+            trace!("Chunked body ended without its terminating line break");
+            self.state = ChunkState::Done;
+        }
+        self.is_done()
+    }
+
+    /// Decode the encoded bytes in `buf[pos..end]`, writing the chunk data to `buf[out..]`.
+    ///
+    /// The data of each chunk is copied as is, by its announced size, so it may contain anything.
+    /// A chunk size line or line break received in part is left for the next call, once more
+    /// bytes are received after it. `out` must not be past `pos`. Returns the new values of `out`
+    /// and `pos`.
+    pub(crate) fn decode(
+        &mut self,
+        buf: &mut [u8],
+        mut out: usize,
+        mut pos: usize,
+        end: usize,
+    ) -> Result<(usize, usize), Error> {
+        debug_assert!(out <= pos);
+        loop {
+            match self.state {
+                ChunkState::Size => {
+                    let line = match find_line(&buf[pos..end]) {
+                        Some(line) => line,
+                        None => break,
+                    };
+                    // Chunk extensions are ignored
+                    let size = core::str::from_utf8(&buf[pos..pos + line])?;
+                    let size = size.split(';').next().unwrap_or("").trim();
+                    let size = usize::from_str_radix(size, 16).map_err(|_| {
+                        warn!("Invalid chunk size: {}", size);
+                        Error::Codec
+                    })?;
+                    pos += line + 2;
+                    self.state = if size == 0 {
+                        ChunkState::Trailer
+                    } else {
+                        ChunkState::Data(size)
+                    };
+                }
+                ChunkState::Data(remaining) => {
+                    let n = core::cmp::min(remaining, end - pos);
+                    if n == 0 {
+                        break;
+                    }
+                    buf.copy_within(pos..pos + n, out);
+                    out += n;
+                    pos += n;
+                    self.state = if n == remaining {
+                        ChunkState::DataEnd
+                    } else {
+                        ChunkState::Data(remaining - n)
+                    };
+                }
+                ChunkState::DataEnd => {
+                    if end - pos < 2 {
+                        break;
+                    }
+                    if &buf[pos..pos + 2] != b"\r\n" {
+                        warn!("Chunk data is not followed by a line break");
+                        return Err(Error::Codec);
+                    }
+                    pos += 2;
+                    self.state = ChunkState::Size;
+                }
+                ChunkState::Trailer => {
+                    let line = match find_line(&buf[pos..end]) {
+                        Some(line) => line,
+                        None => break,
+                    };
+                    pos += line + 2;
+                    if line == 0 {
+                        self.state = ChunkState::Done;
+                    }
+                }
+                ChunkState::Done => break,
+            }
+        }
+        Ok((out, pos))
+    }
+}
+
+/// Return the length of the line at the start of `data`, if its line break was received.
+fn find_line(data: &[u8]) -> Option<usize> {
+    data.windows(2).position(|w| w == b"\r\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use crate::mock::{block_on, MockNetwork};
+
+    #[test]
+    fn test_chunk_data_is_opaque() {
+        // The data holds line breaks and what looks like a terminating chunk
+        let encoded = b"7\r\n\r\n0\r\n\r\n\r\n3;ext=1\r\n\x00\r\n\r\n0\r\nTrailer: x\r\n\r\n";
+        let decoded = b"\r\n0\r\n\r\n\x00\r\n";
+
+        // Feed the encoded body in pieces of every size, as received from the connection
+        for step in 1..=encoded.len() {
+            let mut buf = [0; 64];
+            let mut decoder = ChunkedDecoder::new();
+            let (mut out, mut pos, mut end) = (0, 0, 0);
+            while end < encoded.len() {
+                let n = core::cmp::min(step, encoded.len() - end);
+                buf[end..end + n].copy_from_slice(&encoded[end..end + n]);
+                end += n;
+                (out, pos) = decoder.decode(&mut buf, out, pos, end).unwrap();
+            }
+            assert!(decoder.is_done());
+            assert_eq!(encoded.len(), pos);
+            assert_eq!(decoded, &buf[..out]);
+        }
+    }
+
+    #[test]
+    fn test_stream_to_aligned() {
+        let rx = b"defghijklm";
+        for (buffered, page_size) in [(3, 4), (3, 13), (0, 1)] {
+            let mut tx = [];
+            let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(3);
+            let mut buf = [0; 8];
+            buf[..3].copy_from_slice(b"abc");
+            let mut carryover = Carryover::default();
+            let mut reader = BodyReader::new(&mut conn, &mut carryover, &mut buf, buffered, None, None);
+            let mut page = std::vec![0; page_size];
+            let mut pages = std::vec::Vec::new();
+            let n = block_on(reader.stream_to_aligned(&mut page, |p| pages.push(p.to_vec()))).unwrap();
+
+            let body = [&b"abc"[..buffered], rx].concat();
+            assert_eq!(body.len(), n);
+            assert_eq!(body, pages.concat());
+            let (last, full) = pages.split_last().unwrap();
+            assert!(full.iter().all(|p| p.len() == page_size));
+            assert_eq!((body.len() - 1) % page_size + 1, last.len());
+        }
+    }
+
+    #[test]
+    fn test_invalid_chunk_size() {
+        let mut buf = *b"zz\r\nhello\r\n";
+        let mut decoder = ChunkedDecoder::new();
+        assert!(matches!(decoder.decode(&mut buf, 0, 0, 11), Err(Error::Codec)));
+
+        let mut buf = *b"2\r\nhello\r\n";
+        let mut decoder = ChunkedDecoder::new();
+        assert!(matches!(decoder.decode(&mut buf, 0, 0, 10), Err(Error::Codec)));
+    }
+}
@@ -0,0 +1,76 @@
+//! Strategies for receiving responses into the buffers given to a request.
+//!
+//! The strategy is a type parameter of the [`HttpClient`](crate::client::HttpClient), chosen with
+//! [`with_buffer_strategy`](crate::client::HttpClient::with_buffer_strategy), so that only the
+//! code for the chosen strategy ends up in the binary. The storage a response is parsed from is
+//! any [`ResponseBuffer`].
+// Lifetimes of generic associated types cannot be elided, which the lint does not know about
+#![allow(clippy::needless_lifetimes)]
+
+/// A way to receive the response to a request made with
+/// [`request_buffered`](crate::client::HttpClient::request_buffered).
+pub trait BufferStrategy {
+    /// The buffers given to a request, which the response references.
+    type Buffers<'b>;
+
+    /// Return the buffer receiving the response header and, unless the body is received after
+    /// the header in the same buffer, the buffer receiving the body.
+    fn split<'b>(buffers: Self::Buffers<'b>) -> (&'b mut [u8], Option<&'b mut [u8]>);
+}
+
+/// Receive the response header and body in a single buffer, the body following the header.
+///
+/// This is the default strategy, with which responses are received like they are by
+/// [`request`](crate::client::HttpClient::request).
+pub struct SingleBuffer;
+
+impl BufferStrategy for SingleBuffer {
+    type Buffers<'b> = &'b mut [u8];
+
+    #[inline]
+    fn split<'b>(buffers: Self::Buffers<'b>) -> (&'b mut [u8], Option<&'b mut [u8]>) {
+        (buffers, None)
+    }
+}
+
+/// Receive the response header and body in separate buffers, given as a `(header, body)` pair.
+///
+/// The header buffer must have room for the header and for the body bytes received along with it,
+/// which are then moved to the body buffer. Data of the next response received after the body is
+/// kept for the next request, as with [`SingleBuffer`].
+pub struct SplitBuffers;
+
+impl BufferStrategy for SplitBuffers {
+    type Buffers<'b> = (&'b mut [u8], &'b mut [u8]);
+
+    #[inline]
+    fn split<'b>(buffers: Self::Buffers<'b>) -> (&'b mut [u8], Option<&'b mut [u8]>) {
+        (buffers.0, Some(buffers.1))
+    }
+}
+
+/// Storage receiving a response, which is parsed in place.
+///
+/// The response is parsed from a contiguous slice of the storage, which may already hold the start
+/// of the response. Storage that is not contiguous, such as a circular DMA buffer the network data
+/// lands in, can implement this trait by moving its contents in place so that the received data
+/// starts at the front, which is then parsed without being copied to another buffer.
+pub trait ResponseBuffer {
+    /// Return the storage as a contiguous slice, along with the number of bytes of the response
+    /// already received at its start.
+    fn contiguous(&mut self) -> (&mut [u8], usize);
+}
+
+impl ResponseBuffer for [u8] {
+    #[inline]
+    fn contiguous(&mut self) -> (&mut [u8], usize) {
+        (self, 0)
+    }
+}
+
+impl<const N: usize> ResponseBuffer for [u8; N] {
+    #[inline]
+    fn contiguous(&mut self) -> (&mut [u8], usize) {
+        (self, 0)
+    }
+}
@@ -0,0 +1,354 @@
+//! A small fixed-capacity cache for responses to GET requests.
+use crate::client::{Error, HttpClient};
+use crate::request::*;
+use crate::{Clock, Network};
+use heapless::Vec;
+
+/// A HTTP client serving GET requests from a cache of previous responses.
+///
+/// Successful responses carrying a `Cache-Control: max-age` directive are stored, keyed by path,
+/// and served until they expire. The cache holds up to `ENTRIES` responses of at most `ENTRY_SIZE`
+/// bytes each, counting the path, the response header, the body and the values of the request
+/// headers named in its `Vary` header. A cached response is only served to requests with the same
+/// values for those headers. When full, the least recently used response is evicted.
+pub struct CachingClient<'a, N, C, const ENTRIES: usize, const ENTRY_SIZE: usize>
+where
+    N: Network + 'a,
+    C: Clock,
+{
+    client: HttpClient<'a, N>,
+    clock: C,
+    entries: Vec<CacheEntry<ENTRY_SIZE>, ENTRIES>,
+    uses: u64,
+}
+
+struct CacheEntry<const SIZE: usize> {
+    /// The path, followed by the raw response header, the body and the values of the varying
+    /// request headers, each terminated by a newline.
+    data: Vec<u8, SIZE>,
+    path_len: usize,
+    header_len: usize,
+    /// The length of the body, `None` if the response had no payload.
+    body_len: Option<usize>,
+    status: Status,
+    content_type: Option<ContentType>,
+    expires: u64,
+    last_used: u64,
+}
+
+impl<const SIZE: usize> CacheEntry<SIZE> {
+    fn path(&self) -> &[u8] {
+        &self.data[..self.path_len]
+    }
+
+    /// Whether the request has the same values as the cached response for the headers the response
+    /// varies on.
+    fn matches(&self, request: &Request<'_>) -> bool {
+        let values = &self.data[self.path_len + self.header_len + self.body_len.unwrap_or(0)..];
+        let mut values = values.split(|b| *b == b'\n');
+        self.response()
+            .vary()
+            .all(|name| values.next() == Some(request.header(name).unwrap_or("").as_bytes()))
+    }
+
+    fn response(&self) -> Response<'_> {
+        let header = &self.data[self.path_len..self.path_len + self.header_len];
+        let body = self
+            .body_len
+            .map(|len| &self.data[self.path_len + self.header_len..][..len]);
+        Response {
+            status: self.status,
+            content_type: self.content_type,
+            payload: body,
+            // The header was valid UTF-8 when stored
+            raw_headers: core::str::from_utf8(header).unwrap_or(""),
+        }
+    }
+}
+
+impl<'a, N, C, const ENTRIES: usize, const ENTRY_SIZE: usize> CachingClient<'a, N, C, ENTRIES, ENTRY_SIZE>
+where
+    N: Network + 'a,
+    C: Clock,
+{
+    /// Create a new caching client performing requests with `client`, using `clock` to check
+    /// the freshness of cached responses.
+    pub fn new(client: HttpClient<'a, N>, clock: C) -> Self {
+        Self {
+            client,
+            clock,
+            entries: Vec::new(),
+            uses: 0,
+        }
+    }
+
+    /// Perform a HTTP request like [`HttpClient::request`], serving GET requests from the cache
+    /// when a fresh response is available.
+    ///
+    /// The returned response references data in the provided `rx_buf` argument, or in the cache.
+    pub async fn request<'m>(&'m mut self, request: Request<'m>, rx_buf: &'m mut [u8]) -> Result<Response<'m>, Error> {
+        let now = self.clock.now();
+        let path = request.path.unwrap_or("/");
+        // Entries are looked up by path, which does not include the query parameters
+        let cacheable = matches!(request.method, Method::GET) && request.query.is_none() && request.raw_query.is_none();
+
+        if cacheable {
+            if let Some(i) = self.lookup(&request, now) {
+                trace!("Serving {} from cache", path);
+                self.uses += 1;
+                self.entries[i].last_used = self.uses;
+                return Ok(self.entries[i].response());
+            }
+        }
+
+        let response = self.client.request(request.clone(), rx_buf).await?;
+        if cacheable {
+            if let Some(max_age) = max_age(&response) {
+                self.uses += 1;
+                Self::store(&mut self.entries, &request, &response, now, max_age, self.uses);
+            }
+        }
+        Ok(response)
+    }
+
+    /// Remove the cached response for `path`, if any.
+    pub fn invalidate(&mut self, path: &str) {
+        if let Some(i) = self.entries.iter().position(|e| e.path() == path.as_bytes()) {
+            self.entries.swap_remove(i);
+        }
+    }
+
+    fn lookup(&self, request: &Request<'_>, now: u64) -> Option<usize> {
+        let path = request.path.unwrap_or("/");
+        self.entries
+            .iter()
+            .position(|e| e.path() == path.as_bytes() && e.expires > now && e.matches(request))
+    }
+
+    fn store(
+        entries: &mut Vec<CacheEntry<ENTRY_SIZE>, ENTRIES>,
+        request: &Request<'_>,
+        response: &Response<'_>,
+        now: u64,
+        max_age: u64,
+        last_used: u64,
+    ) {
+        let path = request.path.unwrap_or("/");
+        let body = response.payload.unwrap_or(&[]);
+        let mut data = Vec::new();
+        if data.extend_from_slice(path.as_bytes()).is_err()
+            || data.extend_from_slice(response.raw_headers.as_bytes()).is_err()
+            || data.extend_from_slice(body).is_err()
+        {
+            trace!("Response for {} is too large to cache", path);
+            return;
+        }
+        for name in response.vary() {
+            let value = request.header(name).unwrap_or("");
+            if data.extend_from_slice(value.as_bytes()).is_err() || data.push(b'\n').is_err() {
+                trace!("Response for {} is too large to cache", path);
+                return;
+            }
+        }
+        let entry = CacheEntry {
+            data,
+            path_len: path.len(),
+            header_len: response.raw_headers.len(),
+            body_len: response.payload.map(|body| body.len()),
+            status: response.status,
+            content_type: response.content_type,
+            expires: now.saturating_add(max_age),
+            last_used,
+        };
+
+        if let Some(i) = entries.iter().position(|e| e.path() == path.as_bytes()) {
+            entries.swap_remove(i);
+        } else if entries.is_full() {
+            // Evict expired responses first, then the least recently used one
+            let evict = entries
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, e)| if e.expires <= now { 0 } else { e.last_used })
+                .map(|(i, _)| i);
+            if let Some(i) = evict {
+                entries.swap_remove(i);
+            }
+        }
+        // Only fails if the cache has no room for entries at all
+        entries.push(entry).ok();
+    }
+}
+
+/// Return the number of seconds a response may be cached for, if it is cacheable.
+fn max_age(response: &Response<'_>) -> Option<u64> {
+    if response.status != Status::Ok {
+        return None;
+    }
+    if response.vary().any(|name| name == "*") {
+        return None;
+    }
+    let mut max_age = None;
+    for directive in response.header("cache-control")?.split(',') {
+        let directive = directive.trim();
+        if directive.eq_ignore_ascii_case("no-store") || directive.eq_ignore_ascii_case("no-cache") {
+            return None;
+        } else if let Some((name, value)) = directive.split_once('=') {
+            if name.trim().eq_ignore_ascii_case("max-age") {
+                max_age = value.trim().parse().ok();
+            }
+        }
+    }
+    max_age.filter(|age| *age > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{block_on, MockNetwork};
+    use core::cell::Cell;
+
+    const RESPONSE: &[u8] = b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nContent-Length: 5\r\n\r\nhello";
+
+    #[test]
+    fn test_serve_from_cache() {
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(RESPONSE, &mut tx).with_read_error_after(RESPONSE.len());
+        let now = Cell::new(1000);
+        let mut client: CachingClient<'_, _, _, 2, 128> =
+            CachingClient::new(HttpClient::new(&mut conn, "localhost"), || now.get());
+
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().path("/a").build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        // The connection is exhausted, so this can only be served from the cache
+        now.set(1059);
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().path("/a").build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert_eq!(Some("max-age=60"), response.header("cache-control"));
+
+        now.set(1060);
+        let mut rx_buf = [0; 128];
+        assert!(block_on(client.request(Request::get().path("/a").build(), &mut rx_buf)).is_err());
+    }
+
+    #[test]
+    fn test_empty_payload() {
+        let mut entries: Vec<CacheEntry<128>, 2> = Vec::new();
+        for (path, payload) in [("/a", Some(&b""[..])), ("/b", None)] {
+            let response = Response {
+                status: Status::Ok,
+                content_type: None,
+                payload,
+                raw_headers: "HTTP/1.1 200 OK\r\n\r\n",
+            };
+            CachingClient::<'_, MockNetwork<'_>, fn() -> u64, 2, 128>::store(
+                &mut entries,
+                &Request::get().path(path).build(),
+                &response,
+                0,
+                60,
+                1,
+            );
+        }
+        assert_eq!(Some(&b""[..]), entries[0].response().payload);
+        assert_eq!(None, entries[1].response().payload);
+    }
+
+    #[test]
+    fn test_huge_max_age() {
+        let rx = b"HTTP/1.1 200 OK\r\nCache-Control: max-age=18446744073709551615\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client: CachingClient<'_, _, _, 2, 128> =
+            CachingClient::new(HttpClient::new(&mut conn, "localhost"), || 1000);
+
+        let mut rx_buf = [0; 128];
+        assert!(block_on(client.request(Request::get().path("/a").build(), &mut rx_buf)).is_ok());
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().path("/a").build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_vary() {
+        let rx =
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=60\r\nVary: Accept-Language\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client: CachingClient<'_, _, _, 2, 128> =
+            CachingClient::new(HttpClient::new(&mut conn, "localhost"), || 0);
+        let english = [("Accept-Language", "en")];
+        let german = [("accept-language", "de")];
+
+        let mut rx_buf = [0; 128];
+        let request = Request::get().path("/a").headers(&english).build();
+        assert!(block_on(client.request(request, &mut rx_buf)).is_ok());
+
+        let mut rx_buf = [0; 128];
+        let request = Request::get().path("/a").headers(&english).build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        // The connection is exhausted, so a response in another language cannot be fetched
+        let mut rx_buf = [0; 128];
+        let request = Request::get().path("/a").headers(&german).build();
+        assert!(block_on(client.request(request, &mut rx_buf)).is_err());
+    }
+
+    #[test]
+    fn test_evict_least_recently_used() {
+        let mut entries: Vec<CacheEntry<128>, 2> = Vec::new();
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\n\r\n",
+        };
+        CachingClient::<'_, MockNetwork<'_>, fn() -> u64, 2, 128>::store(
+            &mut entries,
+            &Request::get().path("/a").build(),
+            &response,
+            0,
+            60,
+            1,
+        );
+        CachingClient::<'_, MockNetwork<'_>, fn() -> u64, 2, 128>::store(
+            &mut entries,
+            &Request::get().path("/b").build(),
+            &response,
+            0,
+            60,
+            2,
+        );
+        entries[0].last_used = 3;
+        CachingClient::<'_, MockNetwork<'_>, fn() -> u64, 2, 128>::store(
+            &mut entries,
+            &Request::get().path("/c").build(),
+            &response,
+            0,
+            60,
+            4,
+        );
+        assert!(entries.iter().any(|e| e.path() == b"/a"));
+        assert!(!entries.iter().any(|e| e.path() == b"/b"));
+        assert!(entries.iter().any(|e| e.path() == b"/c"));
+    }
+
+    #[test]
+    fn test_max_age() {
+        let mut response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\nCache-Control: public, max-age=300\r\n\r\n",
+        };
+        assert_eq!(Some(300), max_age(&response));
+        response.raw_headers = "HTTP/1.1 200 OK\r\nCache-Control: no-store, max-age=300\r\n\r\n";
+        assert_eq!(None, max_age(&response));
+        response.raw_headers = "HTTP/1.1 200 OK\r\n\r\n";
+        assert_eq!(None, max_age(&response));
+    }
+}
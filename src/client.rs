@@ -1,20 +1,102 @@
-use crate::Network;
+use crate::{Clock, Network};
 use core::fmt::Write as _;
+use core::future::{ready, Future, Ready};
+use core::marker::PhantomData;
+use core::pin::Pin;
+use core::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
 use core::{num::ParseIntError, str::Utf8Error};
-use embedded_io::Error as _;
-use heapless::String;
+use embedded_io::asynch::{Read, Write};
+use embedded_io::{Error as _, ErrorKind, Io};
+use heapless::{String, Vec};
 
+use crate::body::*;
+use crate::buffer::{BufferStrategy, ResponseBuffer, SingleBuffer};
 use crate::request::*;
+use crate::retry::RetryContext;
 
 /// An async HTTP client that can performs HTTP requests on a connection.
 ///
-/// The connection is borrowed for the lifetime of the client and is not closed.
-pub struct HttpClient<'a, N>
+/// The connection is borrowed for the lifetime of the client and is not closed. Responses to
+/// [`request_buffered`](Self::request_buffered) are received as chosen by the [`BufferStrategy`]
+/// `B`.
+pub struct HttpClient<'a, N, B = SingleBuffer>
 where
     N: Network + 'a,
 {
     connection: &'a mut N,
-    host: &'a str,
+    pub(crate) host: &'a str,
+    default_headers: &'a [(&'a str, &'a str)],
+    write_chunk_size: Option<usize>,
+    max_header_line: Option<usize>,
+    pub(crate) total_timeout: Option<u64>,
+    /// Whether the server is asked to close the connection after each request.
+    close: bool,
+    /// Whether a request was sent asking the server to close the connection.
+    spent: bool,
+    carryover: Carryover,
+    options: ReadOptions<'a>,
+    strategy: PhantomData<B>,
+}
+
+/// The content codings the client can decode, announced in the `Accept-Encoding` header.
+#[cfg(feature = "gzip")]
+const ACCEPT_ENCODING: Option<&str> = Some("gzip");
+#[cfg(not(feature = "gzip"))]
+const ACCEPT_ENCODING: Option<&str> = None;
+
+/// The size of the buffer through which
+/// [`request_with_body_reader`](HttpClient::request_with_body_reader) copies the request body.
+const BODY_READER_CHUNK: usize = 256;
+
+/// The number of bytes of the next response received along with a response body that the client
+/// keeps for the next request. The connection is spent if more are received.
+const CARRYOVER_CAPACITY: usize = 128;
+
+/// How data received past the end of a response body is handled.
+///
+/// Such data belongs to the next response on a persistent connection, but may also be the sign of a
+/// server sending more body than it announced, or of an attempt to smuggle a response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TrailingData {
+    /// Accept the data, which is kept by the client and parsed as the start of the next response on
+    /// a persistent connection.
+    Retain,
+    /// Fail with [`Error::BodyLengthMismatch`] if the server closes the connection after the
+    /// response, as the data cannot belong to another response, and retain it otherwise.
+    RejectOnClose,
+    /// Always fail with [`Error::BodyLengthMismatch`], for servers that never pipeline responses.
+    Reject,
+}
+
+impl Default for TrailingData {
+    fn default() -> Self {
+        TrailingData::Retain
+    }
+}
+
+/// Options controlling how responses are read.
+#[derive(Clone, Copy, Default)]
+struct ReadOptions<'a> {
+    strict_body_length: bool,
+    trailing_data: TrailingData,
+    lenient_status_line: bool,
+    lenient_version_case: bool,
+    lenient_header_end: bool,
+    lenient_chunk_end: bool,
+    /// Whether the request asked for bodies compressed with gzip, so that such bodies are decoded.
+    accept_gzip: bool,
+    on_header: Option<&'a dyn Fn(&str, &str) -> bool>,
+}
+
+impl ReadOptions<'_> {
+    fn chunked_decoder(&self) -> ChunkedDecoder {
+        if self.lenient_chunk_end {
+            ChunkedDecoder::new().with_lenient_end()
+        } else {
+            ChunkedDecoder::new()
+        }
+    }
 }
 
 impl<'a, N> HttpClient<'a, N>
@@ -23,19 +105,259 @@ where
 {
     /// Create a new HTTP client for a given connection handle and a target host.
     pub fn new(connection: &'a mut N, host: &'a str) -> Self {
-        Self { connection, host }
+        Self {
+            connection,
+            host,
+            default_headers: &[],
+            write_chunk_size: None,
+            max_header_line: None,
+            total_timeout: None,
+            close: false,
+            spent: false,
+            carryover: Carryover::default(),
+            options: ReadOptions::default(),
+            strategy: PhantomData,
+        }
+    }
+}
+
+impl<'a, N, B> HttpClient<'a, N, B>
+where
+    N: Network + 'a,
+    B: BufferStrategy,
+{
+    /// Receive the responses to [`request_buffered`](Self::request_buffered) as chosen by the
+    /// buffer strategy `S`. Defaults to [`SingleBuffer`].
+    pub fn with_buffer_strategy<S: BufferStrategy>(self) -> HttpClient<'a, N, S> {
+        HttpClient {
+            connection: self.connection,
+            host: self.host,
+            default_headers: self.default_headers,
+            write_chunk_size: self.write_chunk_size,
+            max_header_line: self.max_header_line,
+            total_timeout: self.total_timeout,
+            close: self.close,
+            spent: self.spent,
+            carryover: self.carryover,
+            options: self.options,
+            strategy: PhantomData,
+        }
+    }
+
+    /// Send `headers` with every request.
+    ///
+    /// A default header is left out of requests setting a header of the same name, compared
+    /// case-insensitively, either as an extra header or through the request builder.
+    pub fn with_default_headers(mut self, headers: &'a [(&'a str, &'a str)]) -> Self {
+        self.default_headers = headers;
+        self
+    }
+
+    /// Limit each write to the underlying connection to at most `chunk_size` bytes.
+    ///
+    /// By default, the request body is handed to the connection in a single write.
+    pub fn with_write_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        self.write_chunk_size.replace(chunk_size);
+        self
+    }
+
+    /// Fail with [`Error::HeaderTooLong`] instead of sending a request header line longer than
+    /// `len` bytes, not counting its line break, for servers rejecting such lines.
+    ///
+    /// By default, header lines of any length are sent.
+    pub fn with_max_header_line(mut self, len: usize) -> Self {
+        self.max_header_line.replace(len);
+        self
+    }
+
+    /// Limit the time spent on all attempts of a request sent with
+    /// [`request_with_retry`](Self::request_with_retry), or on all hops of a request sent with
+    /// [`request_following_redirects`](Self::request_following_redirects), to `seconds`.
+    ///
+    /// Once exceeded, the request fails with [`Error::Timeout`] instead of being retried or
+    /// redirected. The limit is checked between attempts, so an attempt already running is not cut
+    /// short: use [`request_with_timeout`](Self::request_with_timeout) to bound a single exchange.
+    pub fn with_total_timeout(mut self, seconds: u64) -> Self {
+        self.total_timeout.replace(seconds);
+        self
+    }
+
+    /// Send `Connection: close` with every request, asking the server to close the connection once
+    /// it has sent the response.
+    ///
+    /// A response of unknown length is then read until the connection is closed, and the connection
+    /// is spent after the first request: further requests fail with [`Error::ConnectionClosed`]
+    /// without sending anything. This suits one-shot requests on fresh connections, with no
+    /// keep-alive state to track.
+    pub fn with_connection_close(mut self) -> Self {
+        self.close = true;
+        self
+    }
+
+    /// Verify that the complete body was received, failing with [`Error::BodyLengthMismatch`]
+    /// otherwise.
+    ///
+    /// By default, a body cut short by the server closing the connection or by `rx_buf` running
+    /// out of room is returned as is.
+    pub fn with_strict_body_length(mut self) -> Self {
+        self.options.strict_body_length = true;
+        self
+    }
+
+    /// Fail with [`Error::BodyLengthMismatch`] if data follows the body of a response on a
+    /// connection the server is about to close.
+    ///
+    /// This is a shorthand for [`with_trailing_data`](Self::with_trailing_data) with
+    /// [`TrailingData::RejectOnClose`].
+    pub fn with_strict_trailing_data(self) -> Self {
+        self.with_trailing_data(TrailingData::RejectOnClose)
+    }
+
+    /// Set how data received past the end of a response body, as announced by its
+    /// `Content-Length`, is handled.
+    ///
+    /// By default, such data is [retained](TrailingData::Retain).
+    pub fn with_trailing_data(mut self, policy: TrailingData) -> Self {
+        self.options.trailing_data = policy;
+        self
+    }
+
+    /// Skip whitespace, control bytes and a byte order mark preceding the status line of a
+    /// response.
+    ///
+    /// Such a prefix is not valid HTTP, but is sent by some misbehaving gateways. By default, it
+    /// prevents the status line from being found, failing the request with [`Error::Codec`].
+    pub fn with_lenient_status_line(mut self) -> Self {
+        self.options.lenient_status_line = true;
+        self
+    }
+
+    /// Accept a status line whose `HTTP` version token is not in uppercase, such as `http/1.1`.
+    ///
+    /// Such a status line is not valid HTTP, but is sent by some devices. By default, it is not
+    /// recognized, failing the request with [`Error::Codec`].
+    pub fn with_lenient_version_case(mut self) -> Self {
+        self.options.lenient_version_case = true;
+        self
+    }
+
+    /// Call `on_header` with the name and value of each header of a response, as the header is
+    /// parsed.
+    ///
+    /// The headers of interim responses are skipped. If `on_header` returns `false`, parsing stops
+    /// and the request fails with [`Error::HeaderRejected`], without reading the body.
+    pub fn with_on_header(mut self, on_header: &'a dyn Fn(&str, &str) -> bool) -> Self {
+        self.options.on_header.replace(on_header);
+        self
+    }
+
+    /// Accept a response header missing the empty line that terminates it, when the last header
+    /// line is followed by exactly as many bytes as announced by its `Content-Length`.
+    ///
+    /// Such a response is not valid HTTP, but is sent by some devices. As the end of the header is
+    /// guessed from the length of what follows, it may be found early if the connection delivers
+    /// part of a header line of just that length. By default, the header is only terminated by an
+    /// empty line.
+    pub fn with_lenient_header_end(mut self) -> Self {
+        self.options.lenient_header_end = true;
+        self
+    }
+
+    /// Accept a chunked response body ending with the last chunk, when the connection is closed
+    /// before the empty line that should terminate it arrives.
+    ///
+    /// Such a body is not valid HTTP, but is sent by some servers. Any part of a trailer received
+    /// before the connection is closed is ignored. By default, the body fails with
+    /// [`Error::BodyLengthMismatch`] if the connection is closed before the end of the body.
+    pub fn with_lenient_chunk_end(mut self) -> Self {
+        self.options.lenient_chunk_end = true;
+        self
     }
 
     async fn write_data(&mut self, data: &[u8]) -> Result<(), Error> {
-        self.connection.write(data).await.map_err(|e| e.kind())?;
-        Ok(())
+        write_all(self.connection, data, self.write_chunk_size).await
     }
 
     async fn write_str(&mut self, data: &str) -> Result<(), Error> {
         self.write_data(data.as_bytes()).await
     }
 
+    /// Write the query parameters of a request following `query`, the query already written.
+    async fn write_query(&mut self, query: Option<&str>, params: &[(&str, &str)]) -> Result<(), Error> {
+        let mut separator = match query {
+            None => "?",
+            Some(query) if query.is_empty() || query.ends_with('&') => "",
+            Some(_) => "&",
+        };
+        for (name, value) in params {
+            self.write_str(separator).await?;
+            self.write_percent_encoded(name).await?;
+            self.write_str("=").await?;
+            self.write_percent_encoded(value).await?;
+            separator = "&";
+        }
+        Ok(())
+    }
+
+    /// Write `data` with every byte other than an unreserved character percent-encoded.
+    async fn write_percent_encoded(&mut self, data: &str) -> Result<(), Error> {
+        const HEX: &[u8; 16] = b"0123456789ABCDEF";
+        let mut rest = data.as_bytes();
+        while !rest.is_empty() {
+            let unreserved = rest
+                .iter()
+                .position(|b| !(b.is_ascii_alphanumeric() || b"-._~".contains(b)))
+                .unwrap_or(rest.len());
+            self.write_data(&rest[..unreserved]).await?;
+            if let Some(b) = rest.get(unreserved) {
+                self.write_data(&[b'%', HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]])
+                    .await?;
+                rest = &rest[unreserved + 1..];
+            } else {
+                rest = &[];
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the base64 encoding of the concatenated `parts`, without buffering them as a whole.
+    async fn write_base64(&mut self, parts: &[&[u8]]) -> Result<(), Error> {
+        // Encode in whole groups of 3 bytes, so that only the final group is padded
+        let mut group = [0; 48];
+        let mut encoded = [0; 64];
+        let mut len = 0;
+        for part in parts {
+            for b in part.iter() {
+                group[len] = *b;
+                len += 1;
+                if len == group.len() {
+                    let n = base64::encode_config_slice(&group, base64::STANDARD, &mut encoded);
+                    self.write_data(&encoded[..n]).await?;
+                    len = 0;
+                }
+            }
+        }
+        if len > 0 {
+            let n = base64::encode_config_slice(&group[..len], base64::STANDARD, &mut encoded);
+            self.write_data(&encoded[..n]).await?;
+        }
+        Ok(())
+    }
+
+    /// Check that a header line of `len` bytes does not exceed the configured maximum.
+    fn check_header_line(&self, len: usize) -> Result<(), Error> {
+        match self.max_header_line {
+            Some(max) if len > max => {
+                warn!("Header line of {} bytes exceeds the maximum of {}", len, max);
+                Err(Error::HeaderTooLong)
+            }
+            _ => Ok(()),
+        }
+    }
+
     async fn write_header(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        self.check_header_line(key.len() + ": ".len() + value.len())?;
         self.write_str(key).await?;
         self.write_str(": ").await?;
         self.write_str(value).await?;
@@ -48,215 +370,1704 @@ where
     /// be sized to contain the entire response.
     ///
     /// The returned response references data in the provided `rx_buf` argument.
-    pub async fn request<'m>(&'m mut self, request: Request<'m>, rx_buf: &'m mut [u8]) -> Result<Response<'m>, Error> {
-        self.write_str(request.method.as_str()).await?;
-        self.write_str(" ").await?;
-        self.write_str(request.path.unwrap_or("/")).await?;
-        self.write_str(" HTTP/1.1\r\n").await?;
+    ///
+    /// On a persistent connection, data received past the end of the body is the start of the next
+    /// response. It is [retained](TrailingData::Retain) by the client, and parsed as the start of
+    /// the response to the next request, so that requests can be made back to back on the
+    /// connection. If more than 128 bytes are received this way, the connection is spent instead:
+    /// further requests fail with [`Error::ConnectionClosed`].
+    ///
+    /// If the response header or its announced body length does not fit in `rx_buf`, the request
+    /// fails with [`Error::BufferTooSmall`] before reading the body. A body without a
+    /// `Content-Length` or chunked encoding is read until the connection is closed, and fails the
+    /// same way if the connection is still open once `rx_buf` is full, as does a chunked body
+    /// that does not fit.
+    ///
+    /// With the `gzip` feature, the client asks for bodies compressed with gzip, unless the request
+    /// is built with [`no_accept_encoding`](crate::request::RequestBuilder::no_accept_encoding),
+    /// and decodes them into the part of `rx_buf` following the received body. A body with a gzip
+    /// content coding the client did not ask for is returned as received, while one with a gzip
+    /// transfer coding fails with [`Error::UnsupportedEncoding`] without the feature.
+    ///
+    /// `rx_buf` is any [`ResponseBuffer`], such as a byte slice or array, and may already hold the
+    /// start of the response.
+    pub async fn request<'m, R>(&'m mut self, request: Request<'m>, rx_buf: &'m mut R) -> Result<Response<'m>, Error>
+    where
+        R: ResponseBuffer + ?Sized,
+    {
+        let (rx_buf, received) = rx_buf.contiguous();
+        let received = self.send_request_after(&request, rx_buf, received).await?;
+        Self::read_response(self.connection, self.options, &mut self.carryover, rx_buf, received).await
+    }
 
-        self.write_header("Host", self.host).await?;
+    /// Perform a HTTP request like [`request`](Self::request), but keep the response if reading
+    /// the body fails.
+    ///
+    /// Errors writing the request or reading the response header are returned as `Err`. Once the
+    /// header is parsed, the response is always returned, with the payload holding whatever part
+    /// of the body arrived, together with the error that interrupted the body read, if any.
+    pub async fn request_partial<'m, R>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut R,
+    ) -> Result<(Response<'m>, Option<Error>), Error>
+    where
+        R: ResponseBuffer + ?Sized,
+    {
+        let (rx_buf, received) = rx_buf.contiguous();
+        let received = self.send_request_after(&request, rx_buf, received).await?;
+        Self::read_response_partial(self.connection, self.options, &mut self.carryover, rx_buf, received).await
+    }
 
-        if let Some(auth) = request.auth {
-            match auth {
-                Auth::Basic { username, password } => {
-                    let mut combined: String<128> = String::new();
-                    write!(combined, "{}:{}", username, password).map_err(|_| Error::Codec)?;
-                    let mut authz = [0; 256];
-                    let authz_len = base64::encode_config_slice(combined.as_bytes(), base64::STANDARD, &mut authz);
-                    self.write_str("Authorization: Basic ").await?;
-                    self.write_str(unsafe { core::str::from_utf8_unchecked(&authz[..authz_len]) })
-                        .await?;
-                    self.write_str("\r\n").await?;
-                }
+    /// Perform a HTTP request like [`request`](Self::request), measuring the duration of each phase
+    /// of the exchange with `clock`.
+    ///
+    /// The [timings](Timings) are in the unit of the clock, which should be finer than seconds,
+    /// such as milliseconds, to tell the phases apart.
+    #[cfg(feature = "time")]
+    pub async fn request_timed<'m, C>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+        clock: &C,
+    ) -> Result<(Response<'m>, Timings), Error>
+    where
+        C: Clock,
+    {
+        let start = clock.now();
+        let mut received = self.send_request(&request, rx_buf).await?;
+        if received == 0 {
+            received = self.connection.read(rx_buf).await.map_err(|e| e.kind())?;
+            if received == 0 {
+                warn!("Connection closed before the response");
+                return Err(Error::ConnectionClosed);
             }
         }
-        if let Some(content_type) = request.content_type {
-            self.write_header("Content-Type", content_type.as_str()).await?;
-        }
-        if let Some(payload) = request.payload {
-            let mut s: String<32> = String::new();
-            write!(s, "{}", payload.len()).map_err(|_| Error::Codec)?;
-            self.write_header("Content-Length", s.as_str()).await?;
-        }
-        if let Some(extra_headers) = request.extra_headers {
-            for (header, value) in extra_headers.iter() {
-                self.write_header(header, value).await?;
-            }
+        let first_byte = clock.now();
+
+        let (head, pos) = Self::read_final_head(self.connection, self.options, rx_buf, received).await?;
+        let header_end = clock.now();
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let (payload, error) =
+            Self::read_body(self.connection, self.options, &mut self.carryover, &head, rx_buf, pos).await;
+        if let Some(e) = error {
+            return Err(e);
         }
-        self.write_str("\r\n").await?;
-        trace!("Header written");
-        match request.payload {
-            None => Self::read_response(self.connection, rx_buf).await,
-            Some(payload) => {
-                trace!("Writing data");
-                let result = self.connection.write(payload).await;
-                match result {
-                    Ok(_) => Self::read_response(self.connection, rx_buf).await,
-                    Err(e) => {
-                        warn!("Error sending data: {:?}", e.kind());
-                        Err(Error::Network(e.kind()))
-                    }
+        let timings = Timings {
+            time_to_first_byte: first_byte - start,
+            header: header_end - first_byte,
+            body: clock.now() - header_end,
+        };
+        let response = Response {
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload,
+        };
+        Ok((response, timings))
+    }
+
+    /// Perform a HTTP request like [`request`](Self::request), failing with [`Error::Timeout`] if
+    /// `deadline` completes first.
+    ///
+    /// The deadline covers the whole exchange, from writing the request to reading the end of the
+    /// response, so a server sending data slowly cannot keep the request alive past it. It is
+    /// typically a delay of the async timer of the platform, such as
+    /// `embedded_hal_async::delay::DelayUs::delay_ms`. After a timeout, the exchange is left
+    /// incomplete, so further requests fail with [`Error::ConnectionClosed`].
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request_with_timeout<'m, D>(
+        &mut self,
+        request: Request<'_>,
+        rx_buf: &'m mut [u8],
+        deadline: D,
+    ) -> Result<Response<'m>, Error>
+    where
+        D: Future<Output = ()>,
+    {
+        let result = {
+            let exchange = async {
+                let received = self.send_request(&request, rx_buf).await?;
+                Self::read_response(self.connection, self.options, &mut self.carryover, rx_buf, received).await
+            };
+            let mut exchange = exchange;
+            let mut deadline = deadline;
+            // Safety: the futures are shadowed and never moved again
+            let exchange = unsafe { Pin::new_unchecked(&mut exchange) };
+            let deadline = unsafe { Pin::new_unchecked(&mut deadline) };
+            Deadline { exchange, deadline }.await
+        };
+        result.unwrap_or_else(|| {
+            warn!("Request timed out");
+            self.spent = true;
+            Err(Error::Timeout)
+        })
+    }
+
+    /// Perform a HTTP request with an expectation like [`request`](Self::request), sending the
+    /// payload anyway if the server has not started to answer when `delay` completes.
+    ///
+    /// A server that does not support expectations never answers with `100 Continue`, so
+    /// [`request`](Self::request) would wait for it forever. `delay` is typically a delay of about
+    /// a second from the async timer of the platform. Requests without an expectation are sent
+    /// like with [`request`](Self::request).
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request_with_continue_timeout<'m, D>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+        delay: D,
+    ) -> Result<Response<'m>, Error>
+    where
+        D: Future<Output = ()>,
+    {
+        let received = self.send_request_within(&request, rx_buf, 0, delay).await?;
+        Self::read_response(self.connection, self.options, &mut self.carryover, rx_buf, received).await
+    }
+
+    /// Perform a HTTP request like [`request`](Self::request), sending it again for as long as
+    /// `should_retry` asks to.
+    ///
+    /// After each attempt, `should_retry` is called with a [`RetryContext`] describing its outcome,
+    /// using `clock` to measure the time elapsed since the first attempt. If it returns `true`, the
+    /// request is sent again on the same connection. Otherwise, the response or error of the last
+    /// attempt is returned. [`retry_idempotent`](crate::retry::retry_idempotent) is a predicate for
+    /// common transient failures.
+    ///
+    /// If a [total timeout](Self::with_total_timeout) is set, the request fails with
+    /// [`Error::Timeout`] rather than being retried once it is exceeded.
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request_with_retry<'m, C, F>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+        clock: &C,
+        should_retry: F,
+    ) -> Result<Response<'m>, Error>
+    where
+        C: Clock,
+        F: Fn(&RetryContext<'_>) -> bool,
+    {
+        let start = clock.now();
+        let mut attempt = 0;
+        let base = rx_buf.as_ptr();
+        // Only the location of the response in rx_buf is kept across attempts, as rx_buf is reused
+        let layout = loop {
+            attempt += 1;
+            let result = match self.send_request(&request, rx_buf).await {
+                Ok(received) => {
+                    Self::read_response(self.connection, self.options, &mut self.carryover, rx_buf, received)
+                        .await
+                        .map(|r| layout_of(&r, base))
                 }
+                Err(e) => Err(e),
+            };
+            let context = RetryContext {
+                method: request.method,
+                attempt,
+                result: result.as_ref().map(|r| r.0),
+                elapsed: clock.now() - start,
+            };
+            if !should_retry(&context) {
+                break result?;
             }
+            if let Some(timeout) = self.total_timeout.filter(|t| context.elapsed >= *t) {
+                warn!(
+                    "Giving up after {} attempts, exceeding the timeout of {}s",
+                    attempt, timeout
+                );
+                return Err(Error::Timeout);
+            }
+            trace!("Retrying request after attempt {}", attempt);
+        };
+
+        response_at(rx_buf, layout)
+    }
+
+    /// Perform a HTTP request to `path` with an optional JSON `body`, deserializing the JSON
+    /// response body into `T`.
+    ///
+    /// Fails with [`Error::Status`] if the response status is not successful, and with
+    /// [`Error::Codec`] if the response body is not JSON or cannot be deserialized into `T`.
+    #[cfg(feature = "serde")]
+    pub async fn fetch_json<'m, T>(
+        &'m mut self,
+        method: Method,
+        path: &'m str,
+        body: Option<&'m [u8]>,
+        rx_buf: &'m mut [u8],
+    ) -> Result<T, Error>
+    where
+        T: serde::Deserialize<'m>,
+    {
+        let request = Request {
+            method,
+            path: Some(path),
+            payload: body,
+            content_type: body.map(|_| ContentType::ApplicationJson),
+            ..Default::default()
+        };
+        let response = self.request(request, rx_buf).await?.error_for_status()?;
+        if response.body_kind() != BodyKind::Json {
+            warn!("Expected a JSON response, got {:?}", response.header("content-type"));
+            return Err(Error::Codec);
         }
+        let (value, _) = serde_json_core::from_slice(response.payload.unwrap_or(&[])).map_err(|_| Error::Codec)?;
+        Ok(value)
     }
 
-    async fn read_response<'m>(connection: &'m mut N, rx_buf: &'m mut [u8]) -> Result<Response<'m>, Error> {
-        let mut pos = 0;
-        let mut header_end = 0;
-        while pos < rx_buf.len() {
-            let n = connection.read(&mut rx_buf[pos..]).await.map_err(|e| {
-                /*warn!(
-                    "error {:?}, but read data from socket:  {:?}",
-                    defmt::Debug2Format(&e),
-                    defmt::Debug2Format(&core::str::from_utf8(&buf[..pos])),
-                );*/
-                e.kind()
-            })?;
+    /// Start a HTTP request whose body is streamed with the returned writer.
+    ///
+    /// The body is sent with a `Content-Length` header if `body_len` is known, in which case exactly
+    /// that many bytes must be written. Otherwise, it is sent using chunked transfer encoding. Once
+    /// the body is complete, the response is read with [`response`](Self::response).
+    pub async fn request_body(
+        &mut self,
+        request: Request<'_>,
+        body_len: Option<u64>,
+    ) -> Result<BodyWriter<'_, N>, Error> {
+        let framing = match body_len {
+            Some(len) => BodyFraming::ContentLength(len),
+            None => BodyFraming::Chunked,
+        };
+        self.write_head(&request, framing).await?;
+        Ok(BodyWriter::new(self.connection, self.write_chunk_size, framing))
+    }
 
-            pos += n;
+    /// Read the response to a request started with [`request_body`](Self::request_body).
+    ///
+    /// The returned response references data in the provided `rx_buf` argument, which is any
+    /// [`ResponseBuffer`] like with [`request`](Self::request).
+    pub async fn response<'m, R>(&'m mut self, rx_buf: &'m mut R) -> Result<Response<'m>, Error>
+    where
+        R: ResponseBuffer + ?Sized,
+    {
+        let (rx_buf, received) = rx_buf.contiguous();
+        let received = self.carryover.take(rx_buf, received);
+        Self::read_response(self.connection, self.options, &mut self.carryover, rx_buf, received).await
+    }
 
-            // Look for header end
-            if let Some(n) = find_sequence(&rx_buf[..pos], b"\r\n\r\n") {
-                header_end = n + 4;
+    /// Perform a HTTP request like [`request`](Self::request), with a body of `body_len` bytes read
+    /// from `body`, such as a firmware image being uploaded.
+    ///
+    /// The body is sent with a `Content-Length` header, and copied from `body` in chunks through a
+    /// small buffer, so it does not need to fit in memory. Only `body_len` bytes are read from
+    /// `body`, and the request fails with [`Error::BodyLengthMismatch`] if it ends before. The
+    /// payload of `request`, if any, is not sent.
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request_with_body_reader<'m, R>(
+        &'m mut self,
+        request: Request<'m>,
+        body: &mut R,
+        body_len: u64,
+        rx_buf: &'m mut [u8],
+    ) -> Result<Response<'m>, Error>
+    where
+        R: Read,
+    {
+        let mut buf = [0; BODY_READER_CHUNK];
+        let mut writer = self.request_body(request, Some(body_len)).await?;
+        let mut remaining = body_len;
+        while remaining > 0 {
+            let n = body
+                .read(&mut buf[..clamp(BODY_READER_CHUNK, remaining)])
+                .await
+                .map_err(|e| e.kind())?;
+            if n == 0 {
                 break;
             }
+            writer.write(&buf[..n]).await?;
+            remaining -= n as u64;
         }
+        writer.finish().await?;
+        self.response(rx_buf).await
+    }
 
-        // Parse header
-        let mut status = Status::BadRequest;
-        let mut content_type = None;
-        let mut content_length = 0;
+    /// Perform a HTTP request with a body read from `body`, streaming the response body to `sink`.
+    ///
+    /// The request body is copied from `body` through `tx_buf`, and is sent with a `Content-Length`
+    /// header if `body_len` is known, or using chunked transfer encoding otherwise. Once the body is
+    /// sent, the response header is read into `rx_buf`, which is then reused to copy the response
+    /// body to `sink`. Neither body needs to fit in memory.
+    ///
+    /// The body is streamed as received, so no content coding is asked for, and a body with a gzip
+    /// transfer coding fails with [`Error::UnsupportedEncoding`].
+    ///
+    /// The returned response has no payload, as the body has been written to `sink`.
+    pub async fn pump<'m, R, W>(
+        &'m mut self,
+        request: Request<'m>,
+        body: &mut R,
+        body_len: Option<u64>,
+        tx_buf: &mut [u8],
+        sink: &mut W,
+        rx_buf: &'m mut [u8],
+    ) -> Result<Response<'m>, Error>
+    where
+        R: Read,
+        W: Write,
+    {
+        let request = Request {
+            accept_encoding: false,
+            ..request
+        };
+        let mut writer = self.request_body(request, body_len).await?;
+        writer.write_from(body, tx_buf).await?;
+        writer.finish().await?;
 
-        let header = core::str::from_utf8(&rx_buf[..header_end])?;
-        trace!("Received header: {}", header);
+        let received = self.carryover.take(rx_buf, 0);
+        let (head, buffered) = Self::read_final_head(self.connection, self.options, rx_buf, received).await?;
+        head.check_streamable()?;
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let mut reader = BodyReader::new(
+            self.connection,
+            &mut self.carryover,
+            rx_buf,
+            buffered,
+            head.content_length,
+            head.chunked.then(|| self.options.chunked_decoder()),
+        );
+        loop {
+            let data = reader.fill_buf().await?;
+            if data.is_empty() {
+                break;
+            }
+            let n = sink.write(data).await.map_err(|e| e.kind())?;
+            if n == 0 {
+                warn!("Sink accepted no data with {} bytes of body left to write", data.len());
+                return Err(Error::ConnectionClosed);
+            }
+            reader.consume(n);
+        }
 
-        let lines = header.split("\r\n");
-        for line in lines {
-            if line.starts_with("HTTP") {
-                let pos = b"HTTP/N.N ".len();
-                status = line[pos..pos + 3].parse::<u32>()?.into();
-            } else if match_header(line, "content-type") {
-                content_type.replace(line["content-type:".len()..].trim_start().into());
-            } else if match_header(line, "content-length") {
-                content_length = line["content-length:".len()..].trim_start().parse::<usize>()?;
-            }
-        }
-
-        // Copy to start of slice to save space
-        for i in 0..(pos - header_end) {
-            rx_buf[i] = rx_buf[header_end + i];
-        }
-        pos -= header_end;
-
-        let payload = if content_length > 0 {
-            // We might have data fetched already, keep that
-            let content_length = content_length - pos;
-            trace!("READING {} bytes of content", content_length);
-
-            let mut to_read = core::cmp::min(rx_buf.len() - pos, content_length);
-            //let to_copy = core::cmp::min(to_read, pos - header_end);
-            /*
-            trace!(
-                "to_read({}), to_copy({}), header_end({}), pos({})",
-                to_read,
-                to_copy,
-                header_end,
-                pos
-            );
-            */
-            //rx_buf[..to_copy].copy_from_slice(&buf[header_end..header_end + to_copy]);
-
-            // Fetch the remaining data
-            while to_read > 0 {
-                trace!("Fetching {} bytes", to_read);
-                let n = connection
-                    .read(&mut rx_buf[pos..pos + to_read])
-                    .await
-                    .map_err(|e| e.kind())?;
-                pos += n;
-                to_read -= n;
-            }
-            trace!("http response has {} bytes in payload", pos);
-            Some(&rx_buf[..pos])
-        } else {
-            trace!("0 bytes in payload");
-            None
+        Ok(Response {
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload: None,
+        })
+    }
+
+    /// Perform a HTTP request, returning the response header along with a reader streaming the
+    /// response body from the connection.
+    ///
+    /// The response header is read into `rx_buf`, and the rest of `rx_buf` is used by the reader to
+    /// buffer the body, so the body does not need to fit in memory. The returned response has no
+    /// payload, as the body is read with the [`BodyReader`].
+    ///
+    /// The body is streamed as received, so no content coding is asked for, and a body with a gzip
+    /// transfer coding fails with [`Error::UnsupportedEncoding`].
+    pub async fn request_streaming<'m>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+    ) -> Result<(Response<'m>, BodyReader<'m, N>), Error> {
+        let request = Request {
+            accept_encoding: false,
+            ..request
         };
+        let received = self.send_request(&request, rx_buf).await?;
+        let (head, buffered) = Self::read_final_head(self.connection, self.options, rx_buf, received).await?;
+        head.check_streamable()?;
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let reader = BodyReader::new(
+            self.connection,
+            &mut self.carryover,
+            rx_buf,
+            buffered,
+            head.content_length,
+            head.chunked.then(|| self.options.chunked_decoder()),
+        );
 
         let response = Response {
-            status,
-            content_type,
-            payload,
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload: None,
         };
-        //trace!("HTTP response: {:?}", response);
-        Ok(response)
+        Ok((response, reader))
     }
-}
 
-/// Errors that can be returned by the HTTP client.
-#[derive(Debug)]
-#[cfg_attr(feature = "defmt", derive(defmt::Format))]
-pub enum Error {
-    /// An error with the underlying network
-    Network(embedded_io::ErrorKind),
-    /// An error encoding or decoding data
-    Codec,
-}
+    /// Perform a HTTP request, passing the response body to `write_page` in pages of `page.len()`
+    /// bytes, as needed to write a downloaded image to flash.
+    ///
+    /// The response header is read into `rx_buf`, which is then reused to read the body. Every page
+    /// is full, except the last one if the body length is not a multiple of the page size.
+    ///
+    /// The body is streamed as received, so no content coding is asked for, and a body with a gzip
+    /// transfer coding fails with [`Error::UnsupportedEncoding`].
+    ///
+    /// The returned response has no payload, as the body has been passed to `write_page`.
+    pub async fn request_aligned<'m, F>(
+        &'m mut self,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+        page: &mut [u8],
+        write_page: F,
+    ) -> Result<Response<'m>, Error>
+    where
+        F: FnMut(&[u8]),
+    {
+        let request = Request {
+            accept_encoding: false,
+            ..request
+        };
+        let received = self.send_request(&request, rx_buf).await?;
+        let (head, buffered) = Self::read_final_head(self.connection, self.options, rx_buf, received).await?;
+        head.check_streamable()?;
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let mut reader = BodyReader::new(
+            self.connection,
+            &mut self.carryover,
+            rx_buf,
+            buffered,
+            head.content_length,
+            head.chunked.then(|| self.options.chunked_decoder()),
+        );
+        reader.stream_to_aligned(page, write_page).await?;
 
-impl From<embedded_io::ErrorKind> for Error {
-    fn from(e: embedded_io::ErrorKind) -> Error {
-        Error::Network(e)
+        Ok(Response {
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload: None,
+        })
     }
-}
 
-impl From<ParseIntError> for Error {
-    fn from(_: ParseIntError) -> Error {
-        Error::Codec
-    }
-}
+    /// Perform a HTTP request like [`request`](Self::request), receiving the response into
+    /// `buffers` as chosen by the [`BufferStrategy`] of the client.
+    ///
+    /// With [`SplitBuffers`](crate::buffer::SplitBuffers), the header is read into the first
+    /// buffer and the body into the second one. Fails with [`Error::BufferTooSmall`] if more body
+    /// bytes were received along with the header than fit in the body buffer.
+    ///
+    /// The returned response references data in the provided `buffers`.
+    pub async fn request_buffered<'m>(
+        &'m mut self,
+        request: Request<'m>,
+        buffers: B::Buffers<'m>,
+    ) -> Result<Response<'m>, Error> {
+        let (rx_buf, body_buf) = B::split(buffers);
+        let body_buf = match body_buf {
+            Some(body_buf) => body_buf,
+            None => return self.request(request, rx_buf).await,
+        };
 
-impl From<Utf8Error> for Error {
-    fn from(_: Utf8Error) -> Error {
-        Error::Codec
+        let received = self.send_request(&request, rx_buf).await?;
+        let (head, buffered) = Self::read_final_head(self.connection, self.options, rx_buf, received).await?;
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let body_len = head.content_length.filter(|_| !head.chunked);
+        let moved = body_len.map_or(buffered, |len| clamp(buffered, len));
+        if moved > body_buf.len() {
+            warn!("Response body does not fit in {} bytes", body_buf.len());
+            return Err(Error::BufferTooSmall);
+        }
+        body_buf[..moved].copy_from_slice(&rx_buf[..moved]);
+        if moved < buffered {
+            let surplus = &rx_buf[moved..buffered];
+            if let Some(e) = retain_trailing_data(self.options, &head, &mut self.carryover, surplus) {
+                return Err(e);
+            }
+        }
+
+        let (payload, error) = Self::read_body(
+            self.connection,
+            self.options,
+            &mut self.carryover,
+            &head,
+            body_buf,
+            moved,
+        )
+        .await;
+        if let Some(e) = error {
+            return Err(e);
+        }
+        Ok(Response {
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload,
+        })
     }
-}
 
-// Find the needle sequence in the haystack. If found, return the hackstack position
-// where the sequence was found.
-fn find_sequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
-    if haystack.len() < needle.len() {
-        None
-    } else {
-        let mut p = 0;
-        let mut windows = haystack.windows(needle.len());
-        loop {
-            if let Some(w) = windows.next() {
-                if w == needle {
-                    return Some(p);
-                }
-                p += 1;
-            } else {
-                return None;
+    /// Encode a request into `buf` exactly as [`request`](Self::request) would send it, without
+    /// sending it.
+    ///
+    /// Returns the total length of the encoded request, for transports that must be handed
+    /// complete messages. Fails with [`Error::BufferTooSmall`] if the request does not fit.
+    pub fn serialize_request(&self, request: &Request<'_>, buf: &mut [u8]) -> Result<usize, Error> {
+        let mut connection = BufferConnection { buf, len: 0 };
+        let mut client = HttpClient {
+            connection: &mut connection,
+            host: self.host,
+            default_headers: self.default_headers,
+            write_chunk_size: None,
+            max_header_line: self.max_header_line,
+            total_timeout: self.total_timeout,
+            close: self.close,
+            spent: false,
+            carryover: Carryover::default(),
+            options: self.options,
+            strategy: self.strategy,
+        };
+        let result = {
+            let mut write = client.write_request(request);
+            // Safety: the future is shadowed and never moved again
+            let write = unsafe { Pin::new_unchecked(&mut write) };
+            let waker = noop_waker();
+            // Writes to the buffer complete immediately, so the request is written in a single poll
+            match write.poll(&mut Context::from_waker(&waker)) {
+                Poll::Ready(result) => result,
+                Poll::Pending => unreachable!(),
             }
+        };
+        match result {
+            Ok(()) => Ok(connection.len),
+            Err(Error::Network(_)) => Err(Error::BufferTooSmall),
+            Err(e) => Err(e),
         }
     }
-}
 
-fn match_header(line: &str, hdr: &str) -> bool {
-    if line.len() >= hdr.len() {
-        line[0..hdr.len()].eq_ignore_ascii_case(hdr)
-    } else {
-        false
+    /// Send the request, waiting for the server to accept the body before sending it if the
+    /// request has an `Expect` header.
+    ///
+    /// Without an expectation, the head and body are written back to back, without reading from
+    /// the connection. Returns the number of bytes of the response already received into `rx_buf`,
+    /// including those carried over from the previous response.
+    async fn send_request(&mut self, request: &Request<'_>, rx_buf: &mut [u8]) -> Result<usize, Error> {
+        self.send_request_after(request, rx_buf, 0).await
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Send the request like [`send_request`](Self::send_request), the first `received` bytes of
+    /// `rx_buf` already holding part of the response.
+    async fn send_request_after(
+        &mut self,
+        request: &Request<'_>,
+        rx_buf: &mut [u8],
+        received: usize,
+    ) -> Result<usize, Error> {
+        self.send_request_within(request, rx_buf, received, core::future::pending())
+            .await
+    }
 
-    #[test]
-    fn test_sequence() {
+    /// Write `request` like [`send_request`](Self::send_request), sending a payload held back by an
+    /// expectation anyway if the server has not started to answer by the time `delay` completes.
+    async fn send_request_within<D>(
+        &mut self,
+        request: &Request<'_>,
+        rx_buf: &mut [u8],
+        received: usize,
+        delay: D,
+    ) -> Result<usize, Error>
+    where
+        D: Future<Output = ()>,
+    {
+        let mut received = self.carryover.take(rx_buf, received);
+        if request.expectation().is_none() {
+            self.write_request(request).await?;
+            return Ok(received);
+        }
+
+        self.write_head(request, Self::framing(request)).await?;
+        if received == 0 {
+            let read = {
+                let mut read = self.connection.read(rx_buf);
+                let mut delay = delay;
+                // Safety: the futures are shadowed and never moved again
+                let exchange = unsafe { Pin::new_unchecked(&mut read) };
+                let deadline = unsafe { Pin::new_unchecked(&mut delay) };
+                Deadline { exchange, deadline }.await
+            };
+            match read {
+                Some(n) => received = n.map_err(|e| e.kind())?,
+                None => {
+                    // RFC 9110 section 10.1.1: the client must not wait indefinitely for the server
+                    trace!("No answer to the expectation, sending the body");
+                    self.write_payload(request).await?;
+                    return Ok(0);
+                }
+            }
+            if received == 0 {
+                warn!("Connection closed before the response");
+                return Err(Error::ConnectionClosed);
+            }
+        }
+        loop {
+            let (head, buffered) = Self::read_head(self.connection, self.options, rx_buf, received).await?;
+            if !head.informational {
+                // The server answered without waiting for the body, which is not sent. The server
+                // may still read the announced body as the next request, so the connection is spent.
+                trace!("Request body rejected with {:?}", head.status);
+                self.spent |= request.payload.is_some();
+                return Ok(head.header_len + buffered);
+            }
+            Self::discard_head(rx_buf, &head, buffered);
+            received = buffered;
+            if head.status == Status::Continue {
+                self.write_payload(request).await?;
+                return Ok(received);
+            }
+        }
+    }
+
+    async fn write_request(&mut self, request: &Request<'_>) -> Result<(), Error> {
+        self.write_head(request, Self::framing(request)).await?;
+        self.write_payload(request).await
+    }
+
+    fn framing(request: &Request<'_>) -> BodyFraming {
+        match request.payload {
+            Some(payload) => BodyFraming::ContentLength(payload.len() as u64),
+            None => BodyFraming::None,
+        }
+    }
+
+    async fn write_payload(&mut self, request: &Request<'_>) -> Result<(), Error> {
+        if let Some(payload) = request.payload {
+            trace!("Writing data");
+            let result = self.write_data(payload).await;
+            if let Err(e) = result {
+                warn!("Error sending data: {:?}", e);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_head(&mut self, request: &Request<'_>, framing: BodyFraming) -> Result<(), Error> {
+        if self.spent || self.carryover.out_of_sync() {
+            warn!("Connection was closed after the previous request");
+            return Err(Error::ConnectionClosed);
+        }
+        // Check the headers that may come from untrusted input before writing anything, so that an
+        // invalid header cannot inject headers or split the request
+        let extra_headers = request.extra_headers.unwrap_or(&[]);
+        for (header, value) in self.default_headers.iter().chain(extra_headers.iter()) {
+            validate_header(header, value)?;
+        }
+        validate_header("Host", self.host)?;
+        if let Some(etag) = request.if_match {
+            validate_header("If-Match", etag)?;
+        }
+        if let Some(expectation) = request.expect {
+            validate_header("Expect", expectation)?;
+        }
+        if let Some(Auth::Bearer { token }) = request.auth {
+            validate_header("Authorization", token)?;
+        }
+        if let Some(line) = request
+            .raw_request_line
+            .filter(|line| line.contains(|c| c == '\r' || c == '\n'))
+        {
+            warn!("Invalid request line: {}", line);
+            return Err(Error::InvalidHeader);
+        }
+
+        self.spent = self.close;
+        self.options.accept_gzip = request.accept_encoding && ACCEPT_ENCODING.is_some();
+        if let Some(line) = request.raw_request_line {
+            self.write_str(line).await?;
+            self.write_str("\r\n").await?;
+        } else {
+            self.write_str(request.method.as_str()).await?;
+            self.write_str(" ").await?;
+            let path = request.path.unwrap_or("/");
+            self.write_str(path).await?;
+            if let Some(query) = request.raw_query {
+                self.write_str("?").await?;
+                self.write_str(query).await?;
+            }
+            if let Some(params) = request.query.filter(|params| !params.is_empty()) {
+                let query = request
+                    .raw_query
+                    .or_else(|| path.split_once('?').map(|(_, query)| query));
+                self.write_query(query, params).await?;
+            }
+            self.write_str(" HTTP/1.1\r\n").await?;
+        }
+
+        self.write_header("Host", self.host).await?;
+
+        if let Some(auth) = &request.auth {
+            match auth {
+                Auth::Basic { username, password } => {
+                    let credentials_len = username.len() + 1 + password.len();
+                    self.check_header_line("Authorization: Basic ".len() + (credentials_len + 2) / 3 * 4)?;
+                    self.write_str("Authorization: Basic ").await?;
+                    self.write_base64(&[username.as_bytes(), b":", password.as_bytes()])
+                        .await?;
+                    self.write_str("\r\n").await?;
+                }
+                Auth::Bearer { token } => {
+                    self.check_header_line("Authorization: Bearer ".len() + token.len())?;
+                    self.write_str("Authorization: Bearer ").await?;
+                    self.write_str(token).await?;
+                    self.write_str("\r\n").await?;
+                }
+            }
+        }
+        if let Some(content_type) = &request.content_type {
+            self.write_header("Content-Type", content_type.as_str()).await?;
+        }
+        if request.accept_encoding {
+            if let Some(codings) = ACCEPT_ENCODING {
+                self.write_header("Accept-Encoding", codings).await?;
+            }
+        }
+        match framing {
+            BodyFraming::None => {}
+            BodyFraming::ContentLength(len) => {
+                let mut s: String<32> = String::new();
+                write!(s, "{}", len).map_err(|_| Error::Codec)?;
+                self.write_header("Content-Length", s.as_str()).await?;
+            }
+            BodyFraming::Chunked => {
+                self.write_header("Transfer-Encoding", "chunked").await?;
+            }
+        }
+        if let Some(etag) = request.if_match {
+            self.write_header("If-Match", etag).await?;
+        }
+        if let Some(ranges) = request.ranges.filter(|ranges| !ranges.is_empty()) {
+            let len = ranges.iter().map(|range| range.encode().len() + 1).sum::<usize>();
+            self.check_header_line("Range: bytes=".len() + len - 1)?;
+            self.write_str("Range: bytes=").await?;
+            for (i, range) in ranges.iter().enumerate() {
+                if i > 0 {
+                    self.write_str(",").await?;
+                }
+                self.write_str(range.encode().as_str()).await?;
+            }
+            self.write_str("\r\n").await?;
+        }
+        #[cfg(feature = "content-md5")]
+        if let Some(payload) = request.payload.filter(|_| request.content_md5) {
+            let digest = md5::compute(payload);
+            self.write_str("Content-MD5: ").await?;
+            self.write_base64(&[&digest[..]]).await?;
+            self.write_str("\r\n").await?;
+        }
+        if let Some(expectation) = request.expect {
+            self.write_header("Expect", expectation).await?;
+        }
+        if self.close {
+            self.write_header("Connection", "close").await?;
+        }
+        for (header, value) in self.default_headers.iter() {
+            let replaced = self.close && header.eq_ignore_ascii_case("connection");
+            if replaced || Self::overrides_header(request, header) {
+                trace!("Default header {} overridden by the request", header);
+            } else {
+                self.write_header(header, value).await?;
+            }
+        }
+        if let Some(extra_headers) = request.extra_headers {
+            for (header, value) in extra_headers.iter() {
+                if request.omit_credentials && is_credential(header) {
+                    trace!("Leaving out {} header for another origin", header);
+                } else {
+                    self.write_header(header, value).await?;
+                }
+            }
+        }
+        self.write_str("\r\n").await?;
+        trace!("Header written");
+        Ok(())
+    }
+
+    /// Whether the request sets the header `name` itself.
+    fn overrides_header(request: &Request<'_>, name: &str) -> bool {
+        let builtin = if name.eq_ignore_ascii_case("authorization") {
+            request.auth.is_some()
+        } else if name.eq_ignore_ascii_case("content-type") {
+            request.content_type.is_some()
+        } else if name.eq_ignore_ascii_case("if-match") {
+            request.if_match.is_some()
+        } else if name.eq_ignore_ascii_case("range") {
+            request.ranges.is_some()
+        } else {
+            false
+        };
+        builtin || request.header(name).is_some()
+    }
+
+    async fn read_response<'m>(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        carryover: &mut Carryover,
+        rx_buf: &'m mut [u8],
+        received: usize,
+    ) -> Result<Response<'m>, Error> {
+        match Self::read_response_partial(connection, options, carryover, rx_buf, received).await? {
+            (response, None) => Ok(response),
+            (_, Some(e)) => Err(e),
+        }
+    }
+
+    /// Read a response like [`read_response`](Self::read_response), returning the error that
+    /// interrupted reading the body along with the part of the response that was read.
+    ///
+    /// Data of the next response received after the body is recorded in `carryover`.
+    async fn read_response_partial<'m>(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        carryover: &mut Carryover,
+        rx_buf: &'m mut [u8],
+        received: usize,
+    ) -> Result<(Response<'m>, Option<Error>), Error> {
+        let (head, pos) = Self::read_final_head(connection, options, rx_buf, received).await?;
+        let (raw_headers, rx_buf) = rx_buf.split_at_mut(head.header_len);
+        let (payload, error) = Self::read_body(connection, options, carryover, &head, rx_buf, pos).await;
+        let response = Response {
+            status: head.status,
+            content_type: head.content_type,
+            raw_headers: core::str::from_utf8(raw_headers)?,
+            payload,
+        };
+        //trace!("HTTP response: {:?}", response);
+        Ok((response, error))
+    }
+
+    /// Read the body of the response with the parsed `head` into `rx_buf`, of which the first
+    /// `pos` bytes were already received, and decode it if it is compressed with gzip.
+    ///
+    /// Returns the payload, if any, together with the error that interrupted reading it. Data of
+    /// the next response received after the body is recorded in `carryover`.
+    async fn read_body<'m>(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        carryover: &mut Carryover,
+        head: &ResponseHead,
+        rx_buf: &'m mut [u8],
+        pos: usize,
+    ) -> (Option<&'m [u8]>, Option<Error>) {
+        let (len, error) = {
+            let (payload, error) = Self::read_raw_body(connection, options, carryover, head, rx_buf, pos).await;
+            (payload.map(|payload| payload.len()), error)
+        };
+        match len {
+            Some(len) if head.gzip && error.is_none() => Self::decode_gzip(rx_buf, len),
+            _ => (len.map(|len| &rx_buf[..len]), error),
+        }
+    }
+
+    /// Decode the gzip body received at the start of `rx_buf` into the space that follows it.
+    #[cfg(feature = "gzip")]
+    fn decode_gzip(rx_buf: &mut [u8], len: usize) -> (Option<&[u8]>, Option<Error>) {
+        let (body, out) = rx_buf.split_at_mut(len);
+        match crate::gzip::decode(body, out) {
+            Ok(decoded) => {
+                trace!("Decoded {} bytes of gzip body into {} bytes", len, decoded);
+                (if decoded > 0 { Some(&out[..decoded]) } else { None }, None)
+            }
+            Err(e) => (Some(body), Some(e)),
+        }
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    fn decode_gzip(rx_buf: &mut [u8], len: usize) -> (Option<&[u8]>, Option<Error>) {
+        warn!("Response body is compressed with gzip, which is not enabled");
+        (Some(&rx_buf[..len]), Some(Error::UnsupportedEncoding))
+    }
+
+    /// Read the body of the response with the parsed `head` like [`read_body`](Self::read_body),
+    /// without decoding it.
+    async fn read_raw_body<'m>(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        carryover: &mut Carryover,
+        head: &ResponseHead,
+        rx_buf: &'m mut [u8],
+        mut pos: usize,
+    ) -> (Option<&'m [u8]>, Option<Error>) {
+        if head.chunked {
+            let (len, surplus, error) = Self::read_chunked_body(connection, options, rx_buf, pos).await;
+            trace!("http response has {} bytes in payload", len);
+            if surplus > 0 && head.keep_alive && error.is_none() {
+                carryover.set(&rx_buf[len..len + surplus]);
+            }
+            return (if len > 0 { Some(&rx_buf[..len]) } else { None }, error);
+        }
+
+        let mut error = None;
+        if let Some(content_length) = head.content_length.filter(|len| pos as u64 > *len) {
+            // The body is shorter than what was received, so its length fits
+            let surplus = pos - content_length as usize;
+            pos = content_length as usize;
+            error = retain_trailing_data(options, head, carryover, &rx_buf[pos..pos + surplus]);
+        }
+
+        let payload = match head.content_length {
+            Some(content_length) if content_length > rx_buf.len() as u64 => {
+                // Fail before reading further, so that the caller can retry with a larger buffer
+                warn!(
+                    "Response body of {} bytes does not fit in {} bytes",
+                    content_length,
+                    rx_buf.len()
+                );
+                error.replace(Error::BufferTooSmall);
+                if pos > 0 {
+                    Some(&rx_buf[..pos])
+                } else {
+                    None
+                }
+            }
+            Some(content_length) if content_length > 0 => {
+                // We might have data fetched already, keep that
+                let remaining = content_length - pos as u64;
+                trace!("READING {} bytes of content", remaining);
+
+                let mut to_read = clamp(rx_buf.len() - pos, remaining);
+                //let to_copy = core::cmp::min(to_read, pos - header_end);
+                /*
+                trace!(
+                    "to_read({}), to_copy({}), header_end({}), pos({})",
+                    to_read,
+                    to_copy,
+                    header_end,
+                    pos
+                );
+                */
+                //rx_buf[..to_copy].copy_from_slice(&buf[header_end..header_end + to_copy]);
+
+                // Fetch the remaining data
+                while to_read > 0 {
+                    trace!("Fetching {} bytes", to_read);
+                    match connection.read(&mut rx_buf[pos..pos + to_read]).await {
+                        Ok(0) => {
+                            warn!("Connection closed with {} bytes of body remaining", to_read);
+                            break;
+                        }
+                        Ok(n) => {
+                            pos += n;
+                            to_read -= n;
+                        }
+                        Err(e) => {
+                            warn!("Error reading body: {:?}", e.kind());
+                            error.replace(Error::Network(e.kind()));
+                            break;
+                        }
+                    }
+                }
+                trace!("http response has {} bytes in payload", pos);
+                if options.strict_body_length && error.is_none() && pos as u64 != content_length {
+                    warn!("Expected {} bytes of body, got {}", content_length, pos);
+                    error.replace(Error::BodyLengthMismatch);
+                }
+                Some(&rx_buf[..pos])
+            }
+            Some(_) => {
+                trace!("0 bytes in payload");
+                None
+            }
+            None => {
+                warn!("Response body length is unknown, reading until the connection is closed");
+                let mut closed = false;
+                while pos < rx_buf.len() {
+                    match connection.read(&mut rx_buf[pos..]).await {
+                        Ok(0) => {
+                            closed = true;
+                            break;
+                        }
+                        Ok(n) => pos += n,
+                        Err(e) => {
+                            warn!("Error reading body: {:?}", e.kind());
+                            error.replace(Error::Network(e.kind()));
+                            break;
+                        }
+                    }
+                }
+                if !closed && error.is_none() {
+                    // The body is complete only if the connection is closed right after it
+                    match connection.read(&mut [0]).await {
+                        Ok(0) => {}
+                        Ok(_) => {
+                            warn!("Response body does not fit in {} bytes", rx_buf.len());
+                            error.replace(Error::BufferTooSmall);
+                        }
+                        Err(e) => {
+                            warn!("Error reading body: {:?}", e.kind());
+                            error.replace(Error::Network(e.kind()));
+                        }
+                    }
+                }
+                trace!("http response has {} bytes in payload", pos);
+                if pos > 0 {
+                    Some(&rx_buf[..pos])
+                } else {
+                    None
+                }
+            }
+        };
+        (payload, error)
+    }
+
+    /// Read a chunked body and decode it to the start of `buf`, of which the first `received`
+    /// bytes were already read.
+    ///
+    /// Returns the length of the decoded body and of the data received after it, together with the
+    /// error that interrupted it, if any. The undecoded data is moved after the decoded data as it
+    /// is decoded, so `buf` only needs room for the decoded body and the encoded data of a single
+    /// read.
+    async fn read_chunked_body(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        buf: &mut [u8],
+        received: usize,
+    ) -> (usize, usize, Option<Error>) {
+        let mut decoder = options.chunked_decoder();
+        let mut len = 0;
+        let mut end = received;
+        loop {
+            let (decoded, pos) = match decoder.decode(buf, len, len, end) {
+                Ok(result) => result,
+                Err(e) => return (len, 0, Some(e)),
+            };
+            buf.copy_within(pos..end, decoded);
+            end = decoded + end - pos;
+            len = decoded;
+            if decoder.is_done() {
+                if end > len {
+                    trace!("Received {} bytes after the body", end - len);
+                }
+                return (len, end - len, None);
+            }
+
+            if end == buf.len() {
+                warn!("Chunked body does not fit in {} bytes", buf.len());
+                return (len, 0, Some(Error::BufferTooSmall));
+            }
+            match connection.read(&mut buf[end..]).await {
+                Ok(0) if decoder.finish() => return (len, 0, None),
+                Ok(0) => {
+                    warn!("Connection closed before the end of the chunked body");
+                    return (len, 0, Some(Error::BodyLengthMismatch));
+                }
+                Ok(n) => end += n,
+                Err(e) => {
+                    warn!("Error reading body: {:?}", e.kind());
+                    return (len, 0, Some(Error::Network(e.kind())));
+                }
+            }
+        }
+    }
+
+    /// Read and parse the response header into `rx_buf`.
+    ///
+    /// The header is kept at the start of `rx_buf`, followed by any body bytes received along with
+    /// it, whose count is returned together with the parsed header. The first `received` bytes of
+    /// `rx_buf` hold the part of the response already received.
+    async fn read_head(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        rx_buf: &mut [u8],
+        received: usize,
+    ) -> Result<(ResponseHead, usize), Error> {
+        let mut pos = received;
+        let mut header_end = find_header_end(&rx_buf[..pos], options).unwrap_or(0);
+        let mut skip_prefix = options.lenient_status_line && pos == 0;
+        while header_end == 0 && pos < rx_buf.len() {
+            let n = connection.read(&mut rx_buf[pos..]).await.map_err(|e| {
+                /*warn!(
+                    "error {:?}, but read data from socket:  {:?}",
+                    defmt::Debug2Format(&e),
+                    defmt::Debug2Format(&core::str::from_utf8(&buf[..pos])),
+                );*/
+                e.kind()
+            })?;
+            if n == 0 {
+                warn!("Connection closed after {} bytes of response header", pos);
+                return Err(Error::ConnectionClosed);
+            }
+
+            pos += n;
+
+            if skip_prefix {
+                let prefix = status_line_prefix(&rx_buf[..pos]);
+                if prefix > 0 {
+                    warn!("Skipping {} bytes before the status line", prefix);
+                    rx_buf.copy_within(prefix..pos, 0);
+                    pos -= prefix;
+                }
+                // Keep skipping until more than a partial byte order mark has been received
+                skip_prefix = BOM.starts_with(&rx_buf[..pos]);
+            }
+
+            // Look for header end
+            if let Some(end) = find_header_end(&rx_buf[..pos], options) {
+                header_end = end;
+                break;
+            }
+        }
+        if header_end == 0 {
+            warn!("Response header does not fit in {} bytes", rx_buf.len());
+            return Err(Error::BufferTooSmall);
+        }
+
+        // Parse header
+        let mut status = None;
+        let mut content_type = None;
+        let mut content_length = None;
+        let mut keep_alive = true;
+        let mut informational = false;
+        let mut chunked = false;
+        let mut gzip = false;
+
+        let header = core::str::from_utf8(&rx_buf[..header_end])?;
+        trace!("Received header: {}", header);
+
+        let lines = header.split("\r\n");
+        for (i, line) in lines.enumerate() {
+            // Only the first line can be the status line, even if a header line starts like one
+            let status_line = i == 0
+                && if options.lenient_version_case {
+                    match_header(line, "HTTP")
+                } else {
+                    line.starts_with("HTTP")
+                };
+            // The status line comes first, so interim responses are known by the time of their headers
+            if let Some(on_header) = options.on_header.filter(|_| !status_line && !informational) {
+                if let Some((name, value)) = line.split_once(':') {
+                    if !on_header(name.trim(), value.trim()) {
+                        warn!("Response header {} rejected", name);
+                        return Err(Error::HeaderRejected);
+                    }
+                }
+            }
+            if status_line {
+                let (code, _reason) = parse_status_line(line).ok_or_else(|| {
+                    warn!("Invalid status line: {}", line);
+                    Error::Codec
+                })?;
+                status.replace(code.into());
+                // Switching protocols ends the exchange, as far as HTTP is concerned
+                informational = (100..200).contains(&code) && code != 101;
+                // HTTP/1.0 connections are closed unless asked otherwise
+                keep_alive = !match_header(line, "HTTP/1.0");
+            } else if match_header(line, "content-type") {
+                // The parameters, such as the charset, are parsed from the raw header when asked for
+                let value = &line["content-type:".len()..];
+                content_type.replace(value.split(';').next().unwrap_or(value).trim().into());
+            } else if match_header(line, "content-length") {
+                content_length.replace(line["content-length:".len()..].trim_start().parse::<u64>()?);
+            } else if match_header(line, "transfer-encoding") {
+                chunked = parse_transfer_encoding(&line["transfer-encoding:".len()..], chunked, &mut gzip)?;
+            } else if match_header(line, "content-encoding") {
+                if line["content-encoding:".len()..].split(',').any(is_gzip) {
+                    if options.accept_gzip {
+                        gzip = true;
+                    } else {
+                        trace!("Keeping the body compressed with gzip, which was not asked for");
+                    }
+                }
+            } else if match_header(line, "connection") {
+                for option in line["connection:".len()..].split(',') {
+                    if option.trim().eq_ignore_ascii_case("close") {
+                        keep_alive = false;
+                    } else if option.trim().eq_ignore_ascii_case("keep-alive") {
+                        keep_alive = true;
+                    }
+                }
+            }
+        }
+
+        // A head without a status line is not a response at all
+        let status: Status = status.ok_or_else(|| {
+            warn!("Missing status line");
+            Error::Codec
+        })?;
+
+        // The chunked encoding takes precedence over the announced length
+        if chunked {
+            if let Some(n) = content_length.take() {
+                warn!("Ignoring content-length of {} for chunked response", n);
+            }
+        }
+
+        // These responses never have a body, regardless of what the header claims
+        if matches!(status, Status::NoContent | Status::NotModified) {
+            if let Some(n) = content_length.filter(|n| *n > 0) {
+                warn!("Ignoring content-length of {} for bodiless response", n);
+            }
+            content_length.replace(0);
+            chunked = false;
+            gzip = false;
+        }
+
+        let head = ResponseHead {
+            status,
+            content_type,
+            content_length,
+            keep_alive,
+            informational,
+            chunked,
+            gzip,
+            header_len: header_end,
+        };
+        Ok((head, pos - header_end))
+    }
+
+    /// Read and parse the header of the final response into `rx_buf` like
+    /// [`read_head`](Self::read_head), skipping interim responses.
+    async fn read_final_head(
+        connection: &mut N,
+        options: ReadOptions<'_>,
+        rx_buf: &mut [u8],
+        mut received: usize,
+    ) -> Result<(ResponseHead, usize), Error> {
+        loop {
+            let (head, buffered) = Self::read_head(connection, options, rx_buf, received).await?;
+            if !head.informational {
+                return Ok((head, buffered));
+            }
+            trace!("Skipping interim response {:?}", head.status);
+            Self::discard_head(rx_buf, &head, buffered);
+            received = buffered;
+        }
+    }
+
+    /// Drop the header of an interim response from `rx_buf`, keeping what was received after it.
+    fn discard_head(rx_buf: &mut [u8], head: &ResponseHead, buffered: usize) {
+        rx_buf.copy_within(head.header_len..head.header_len + buffered, 0);
+    }
+}
+
+/// The parsed header of a response.
+struct ResponseHead {
+    status: Status,
+    content_type: Option<ContentType>,
+    /// The body length, or `None` if the body is delimited by the connection closing.
+    content_length: Option<u64>,
+    /// Whether the server keeps the connection open after the response.
+    keep_alive: bool,
+    /// Whether this is an interim response, followed by another response.
+    informational: bool,
+    /// Whether the body uses the chunked transfer encoding.
+    chunked: bool,
+    /// Whether the body is compressed with gzip, as a content or transfer coding.
+    gzip: bool,
+    /// The length of the header, including the terminating empty line.
+    header_len: usize,
+}
+
+/// Handle `data` received past the end of the body of the response with `head` as set by the
+/// trailing data policy of `options`, recording it in `carryover` if it is accepted.
+///
+/// Returns the error failing the response if the data is rejected.
+fn retain_trailing_data(
+    options: ReadOptions<'_>,
+    head: &ResponseHead,
+    carryover: &mut Carryover,
+    data: &[u8],
+) -> Option<Error> {
+    let error = match options.trailing_data {
+        TrailingData::RejectOnClose if !head.keep_alive => {
+            warn!("Received {} bytes after the body on a closing connection", data.len());
+            Some(Error::BodyLengthMismatch)
+        }
+        TrailingData::Reject => {
+            warn!("Received {} bytes after the body", data.len());
+            Some(Error::BodyLengthMismatch)
+        }
+        _ => {
+            trace!("Received {} bytes after the body", data.len());
+            None
+        }
+    };
+    if head.keep_alive && error.is_none() {
+        carryover.set(data);
+    }
+    error
+}
+
+impl ResponseHead {
+    /// Check that the body can be streamed as received, which a gzip transfer coding prevents.
+    fn check_streamable(&self) -> Result<(), Error> {
+        if self.gzip {
+            warn!("Streamed response body is compressed with gzip");
+            return Err(Error::UnsupportedEncoding);
+        }
+        Ok(())
+    }
+}
+
+/// The status, content type, header length and payload location of a response in the buffer that
+/// received it, kept in place of the response while the buffer is borrowed again.
+///
+/// The payload is located by its offset and length, as a decoded payload does not follow the header.
+pub(crate) type Layout = (Status, Option<ContentType>, usize, Option<(usize, usize)>);
+
+/// Return the layout of `response`, received into the buffer starting at `base`.
+pub(crate) fn layout_of(response: &Response<'_>, base: *const u8) -> Layout {
+    let payload = response
+        .payload
+        .map(|payload| (payload.as_ptr() as usize - base as usize, payload.len()));
+    (
+        response.status,
+        response.content_type,
+        response.raw_headers.len(),
+        payload,
+    )
+}
+
+/// Return the response with `layout` in `rx_buf`, the buffer that received it.
+pub(crate) fn response_at(rx_buf: &[u8], layout: Layout) -> Result<Response<'_>, Error> {
+    let (status, content_type, header_len, payload) = layout;
+    Ok(Response {
+        status,
+        content_type,
+        raw_headers: core::str::from_utf8(&rx_buf[..header_len])?,
+        payload: payload.map(|(start, len)| &rx_buf[start..start + len]),
+    })
+}
+
+/// Data of the next response received along with the previous one, copied out of the buffer that
+/// received it.
+#[derive(Default)]
+pub(crate) struct Carryover {
+    data: Vec<u8, CARRYOVER_CAPACITY>,
+    /// Whether data of the next response had to be dropped, so that the connection is out of sync.
+    lost: bool,
+    /// Whether the body of the previous response is streamed by a [`BodyReader`] that has not
+    /// reached its end, so that the connection is out of sync.
+    pub(crate) unread_body: bool,
+}
+
+impl Carryover {
+    /// Whether the connection no longer is at the start of the next response.
+    fn out_of_sync(&self) -> bool {
+        self.lost || self.unread_body
+    }
+
+    /// Record `data` as the start of the next response.
+    pub(crate) fn set(&mut self, data: &[u8]) {
+        self.data.clear();
+        if self.data.extend_from_slice(data).is_err() {
+            warn!("Dropping {} bytes of the next response, which do not fit", data.len());
+            self.lost = true;
+        }
+    }
+
+    /// Move the carried over data to the start of `rx_buf`, before the `received` bytes of the
+    /// response already in it, returning the length of the response received.
+    fn take(&mut self, rx_buf: &mut [u8], received: usize) -> usize {
+        let len = self.data.len();
+        if len + received > rx_buf.len() {
+            warn!("Dropping {} bytes of the next response, which do not fit", len);
+            self.lost = true;
+            self.data.clear();
+            return received;
+        }
+        rx_buf.copy_within(..received, len);
+        rx_buf[..len].copy_from_slice(&self.data);
+        self.data.clear();
+        len + received
+    }
+}
+
+/// Errors that can be returned by the HTTP client.
+#[derive(Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// An error with the underlying network
+    Network(embedded_io::ErrorKind),
+    /// An error encoding or decoding data
+    Codec,
+    /// The received body length does not match the length announced by the server
+    BodyLengthMismatch,
+    /// The provided buffer is too small to hold the data
+    BufferTooSmall,
+    /// The connection was closed, or accepted no more data, before the exchange was complete
+    ConnectionClosed,
+    /// A header name or value contains characters that are not allowed in it
+    InvalidHeader,
+    /// A request header line exceeds the configured maximum length
+    HeaderTooLong,
+    /// The request did not complete in time
+    Timeout,
+    /// The server answered with a status other than success
+    Status(Status),
+    /// A response header was rejected by the [header callback](HttpClient::with_on_header)
+    HeaderRejected,
+    /// A response expected to be empty has a body
+    UnexpectedBody,
+    /// The response was still a redirect once the maximum number of redirects was followed
+    TooManyRedirects,
+    /// The transfer codings of the response are malformed, or cannot be decoded
+    InvalidTransferEncoding,
+    /// The response body is compressed with gzip, and the `gzip` feature is not enabled or the body
+    /// is streamed
+    UnsupportedEncoding,
+    /// The server did not accept a WebSocket upgrade as requested
+    #[cfg(feature = "websocket")]
+    WebSocketHandshakeFailed,
+}
+
+impl From<embedded_io::ErrorKind> for Error {
+    fn from(e: embedded_io::ErrorKind) -> Error {
+        Error::Network(e)
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(_: ParseIntError) -> Error {
+        Error::Codec
+    }
+}
+
+impl embedded_io::Error for Error {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Network(kind) => *kind,
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+impl From<Utf8Error> for Error {
+    fn from(_: Utf8Error) -> Error {
+        Error::Codec
+    }
+}
+
+/// The durations of the phases of a request made with
+/// [`request_timed`](HttpClient::request_timed), in the unit of the clock used.
+#[cfg(feature = "time")]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Timings {
+    /// From starting to send the request to receiving the first byte of the response.
+    pub time_to_first_byte: u64,
+    /// From the first byte of the response to the end of its header, including interim responses.
+    pub header: u64,
+    /// From the end of the header to the end of the body.
+    pub body: u64,
+}
+
+/// A connection writing into a buffer, used to encode requests without sending them.
+struct BufferConnection<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+#[derive(Debug)]
+struct BufferFull;
+
+impl embedded_io::Error for BufferFull {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+impl Io for BufferConnection<'_> {
+    type Error = BufferFull;
+}
+
+impl Read for BufferConnection<'_> {
+    type ReadFuture<'a> = Ready<Result<usize, BufferFull>> where Self: 'a;
+
+    fn read<'a>(&'a mut self, _: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        ready(Ok(0))
+    }
+}
+
+impl Write for BufferConnection<'_> {
+    type WriteFuture<'a> = Ready<Result<usize, BufferFull>> where Self: 'a;
+    type FlushFuture<'a> = Ready<Result<(), BufferFull>> where Self: 'a;
+
+    fn write<'a>(&'a mut self, data: &'a [u8]) -> Self::WriteFuture<'a> {
+        let n = core::cmp::min(data.len(), self.buf.len() - self.len);
+        if n == 0 && !data.is_empty() {
+            return ready(Err(BufferFull));
+        }
+        self.buf[self.len..self.len + n].copy_from_slice(&data[..n]);
+        self.len += n;
+        ready(Ok(n))
+    }
+
+    fn flush(&mut self) -> Self::FlushFuture<'_> {
+        ready(Ok(()))
+    }
+}
+
+/// A future completing with the output of `exchange`, or with `None` if `deadline` completes
+/// first.
+struct Deadline<'f, F, D> {
+    exchange: Pin<&'f mut F>,
+    deadline: Pin<&'f mut D>,
+}
+
+impl<F, D> Future for Deadline<'_, F, D>
+where
+    F: Future,
+    D: Future<Output = ()>,
+{
+    type Output = Option<F::Output>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(output) = this.exchange.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        this.deadline.as_mut().poll(cx).map(|_| None)
+    }
+}
+
+/// A waker that does nothing, for polling futures that never wait.
+pub(crate) fn noop_waker() -> Waker {
+    fn noop_raw_waker() -> RawWaker {
+        fn clone(_: *const ()) -> RawWaker {
+            noop_raw_waker()
+        }
+        fn noop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, noop, noop, noop);
+        RawWaker::new(core::ptr::null(), &VTABLE)
+    }
+    unsafe { Waker::from_raw(noop_raw_waker()) }
+}
+
+// Find the needle sequence in the haystack. If found, return the hackstack position
+// where the sequence was found.
+pub(crate) fn find_sequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if haystack.len() < needle.len() {
+        None
+    } else {
+        let mut p = 0;
+        let mut windows = haystack.windows(needle.len());
+        loop {
+            if let Some(w) = windows.next() {
+                if w == needle {
+                    return Some(p);
+                }
+                p += 1;
+            } else {
+                return None;
+            }
+        }
+    }
+}
+
+// Return the length of the response header at the start of data, if it was received in full.
+fn find_header_end(data: &[u8], options: ReadOptions<'_>) -> Option<usize> {
+    if let Some(n) = find_sequence(data, b"\r\n\r\n") {
+        return Some(n + 4);
+    }
+    if !options.lenient_header_end {
+        return None;
+    }
+    // Take the last complete line as the end of the header if the announced body follows it
+    let end = data.windows(2).rposition(|w| w == b"\r\n")? + 2;
+    let header = core::str::from_utf8(&data[..end]).ok()?;
+    let line = header.split("\r\n").find(|line| match_header(line, "content-length"))?;
+    let content_length: u64 = line["content-length:".len()..].trim().parse().ok()?;
+    if content_length > 0 && (data.len() - end) as u64 == content_length {
+        warn!("Response header is missing its terminating empty line");
+        Some(end)
+    } else {
+        None
+    }
+}
+
+// The UTF-8 byte order mark.
+const BOM: &[u8] = b"\xEF\xBB\xBF";
+
+// Return the length of the whitespace, control bytes and byte order marks at the start of data.
+fn status_line_prefix(data: &[u8]) -> usize {
+    let mut len = 0;
+    loop {
+        if data[len..].starts_with(BOM) {
+            len += BOM.len();
+        } else if data
+            .get(len)
+            .filter(|b| b.is_ascii_whitespace() || b.is_ascii_control())
+            .is_some()
+        {
+            len += 1;
+        } else {
+            return len;
+        }
+    }
+}
+
+// Check that a header name is a token and that its value has no control characters besides tabs.
+fn validate_header(name: &str, value: &str) -> Result<(), Error> {
+    let valid_name = !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b));
+    let valid_value = value.bytes().all(|b| b == b'\t' || !b.is_ascii_control());
+    if valid_name && valid_value {
+        Ok(())
+    } else {
+        warn!("Invalid header: {}", name);
+        Err(Error::InvalidHeader)
+    }
+}
+
+/// Add the codings of the `Transfer-Encoding` header `value` to those of the previous header lines,
+/// given by whether they ended with `chunked`, and return whether the body is chunked.
+///
+/// The codings are listed in the order applied, so the chunked coding delimiting the body must
+/// come last and is decoded first, before the gzip coding recorded in `gzip`. Fails with
+/// [`Error::InvalidTransferEncoding`] if a coding follows it, or for codings other than `chunked`,
+/// `gzip` and `identity`.
+fn parse_transfer_encoding(value: &str, mut chunked: bool, gzip: &mut bool) -> Result<bool, Error> {
+    for coding in value.split(',').map(str::trim).filter(|coding| !coding.is_empty()) {
+        if chunked {
+            warn!("Transfer coding {} applied after chunked", coding);
+            return Err(Error::InvalidTransferEncoding);
+        }
+        if coding.eq_ignore_ascii_case("chunked") {
+            chunked = true;
+        } else if is_gzip(coding) && !*gzip {
+            *gzip = true;
+        } else if !coding.eq_ignore_ascii_case("identity") {
+            warn!("Unsupported transfer coding {}", coding);
+            return Err(Error::InvalidTransferEncoding);
+        }
+    }
+    Ok(chunked)
+}
+
+fn is_gzip(coding: &str) -> bool {
+    let coding = coding.trim();
+    coding.eq_ignore_ascii_case("gzip") || coding.eq_ignore_ascii_case("x-gzip")
+}
+
+/// Whether the header `name` carries credentials, which are only sent to the origin they are for.
+fn is_credential(name: &str) -> bool {
+    ["authorization", "proxy-authorization", "cookie"]
+        .iter()
+        .any(|credential| name.trim().eq_ignore_ascii_case(credential))
+}
+
+fn match_header(line: &str, hdr: &str) -> bool {
+    if line.len() >= hdr.len() {
+        line[0..hdr.len()].eq_ignore_ascii_case(hdr)
+    } else {
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::buffer::{ResponseBuffer, SplitBuffers};
+    use crate::mock::{block_on, MockError, MockNetwork};
+    use embedded_io::ErrorKind;
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        find_sequence(haystack, needle).is_some()
+    }
+
+    #[test]
+    fn test_sequence() {
         assert_eq!(Some(0), find_sequence(b"\r\n\r\n", b"\r\n\r\n"));
         assert_eq!(Some(3), find_sequence(b"foo\r\n\r\n", b"\r\n\r\n"));
         assert_eq!(Some(0), find_sequence(b"\r\n\r\nfoo", b"\r\n\r\n"));
@@ -266,10 +2077,1589 @@ mod tests {
     }
 
     #[test]
-    fn test_match_header() {
-        assert!(match_header("Content-Length: 4", "Content-Length"));
-        assert!(match_header("content-length: 4", "Content-Length"));
-        assert!(match_header("Content-length: 4", "Content-Length"));
-        assert!(!match_header("Content-type: application/json", "Content-Length"));
+    fn test_match_header() {
+        assert!(match_header("Content-Length: 4", "Content-Length"));
+        assert!(match_header("content-length: 4", "Content-Length"));
+        assert!(match_header("Content-length: 4", "Content-Length"));
+        assert!(!match_header("Content-type: application/json", "Content-Length"));
+    }
+
+    #[test]
+    fn test_short_reads() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(1);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_partial_writes() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx).with_write_chunk_size(3);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let request = Request::post()
+            .path("/upload")
+            .payload(b"0123456789")
+            .no_accept_encoding()
+            .build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nContent-Length: 10\r\n\r\n0123456789",
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_partial_response_on_body_error() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert!(matches!(error, Some(Error::Network(ErrorKind::Other))));
+    }
+
+    #[test]
+    fn test_request_fails_on_body_error() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        assert!(block_on(client.request(Request::get().build(), &mut rx_buf)).is_err());
+    }
+
+    #[test]
+    fn test_write_chunk_size() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_write_chunk_size(4);
+        let mut rx_buf = [0; 64];
+        let request = Request::post().payload(b"0123456789").build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert!(conn.largest_write() <= 4);
+        assert!(conn.written().ends_with(b"\r\n\r\n0123456789"));
+    }
+
+    #[test]
+    fn test_pump() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut source = MockNetwork::new(b"request body", &mut []);
+        let mut sink_buf = [0; 16];
+        let mut sink = MockNetwork::new(b"", &mut sink_buf);
+        let mut tx_buf = [0; 8];
+        let mut rx_buf = [0; 48];
+        let response = block_on(client.pump(
+            Request::post().build(),
+            &mut source,
+            None,
+            &mut tx_buf,
+            &mut sink,
+            &mut rx_buf,
+        ))
+        .unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(b"hello world", sink.written());
+        assert!(conn
+            .written()
+            .ends_with(b"Transfer-Encoding: chunked\r\n\r\n8\r\nrequest \r\n4\r\nbody\r\n0\r\n\r\n"));
+    }
+
+    #[test]
+    fn test_pump_closed_sink() {
+        /// A sink that accepts no data.
+        struct Closed;
+
+        impl Io for Closed {
+            type Error = ErrorKind;
+        }
+
+        impl Write for Closed {
+            type WriteFuture<'a> = Ready<Result<usize, ErrorKind>> where Self: 'a;
+            type FlushFuture<'a> = Ready<Result<(), ErrorKind>> where Self: 'a;
+
+            fn write<'a>(&'a mut self, _: &'a [u8]) -> Self::WriteFuture<'a> {
+                ready(Ok(0))
+            }
+
+            fn flush(&mut self) -> Self::FlushFuture<'_> {
+                ready(Ok(()))
+            }
+        }
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut source = MockNetwork::new(b"", &mut []);
+        let mut tx_buf = [0; 8];
+        let mut rx_buf = [0; 48];
+        let result = block_on(client.pump(
+            Request::get().build(),
+            &mut source,
+            Some(0),
+            &mut tx_buf,
+            &mut Closed,
+            &mut rx_buf,
+        ));
+        assert!(matches!(result, Err(Error::ConnectionClosed)));
+    }
+
+    #[test]
+    fn test_no_content_ignores_content_length() {
+        let rx = b"HTTP/1.1 204 No Content\r\nContent-Length: 10\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::NoContent, response.status);
+        assert_eq!(None, response.payload);
+    }
+
+    #[test]
+    fn test_not_modified_ignores_content_length() {
+        let rx = b"HTTP/1.1 304 Not Modified\r\nContent-Length: 10\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::NotModified, response.status);
+        assert_eq!(None, response.payload);
+    }
+
+    #[test]
+    fn test_strict_body_length() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_strict_body_length();
+        let mut rx_buf = [0; 64];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert!(matches!(error, Some(Error::BodyLengthMismatch)));
+    }
+
+    #[test]
+    fn test_short_body_without_strict_body_length() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_basic_auth_long_credentials() {
+        // Lengths around the groups the credentials are encoded in, up to a few hundred bytes
+        for (username_len, password_len) in [(100, 100), (47, 64), (48, 47), (20, 300)] {
+            let username = "u".repeat(username_len);
+            let password = "p".repeat(password_len);
+            let combined = std::format!("{}:{}", username, password);
+            let mut expected = [0; 512];
+            let n = base64::encode_config_slice(combined.as_bytes(), base64::STANDARD, &mut expected);
+
+            let mut tx = [0; 640];
+            let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 64];
+            let request = Request::get().basic_auth(&username, &password).build();
+            block_on(client.request(request, &mut rx_buf)).unwrap();
+
+            let header = [b"Authorization: Basic ", &expected[..n], b"\r\n"].concat();
+            assert!(contains(conn.written(), &header), "{}:{}", username_len, password_len);
+        }
+    }
+
+    #[test]
+    fn test_query() {
+        let params = [("q", "a b&c=d"), ("lang", "fr"), ("name", "Zoë~")];
+        let cases = [
+            (
+                "/search",
+                "GET /search?q=a%20b%26c%3Dd&lang=fr&name=Zo%C3%AB~ HTTP/1.1\r\n",
+            ),
+            (
+                "/search?v=1",
+                "GET /search?v=1&q=a%20b%26c%3Dd&lang=fr&name=Zo%C3%AB~ HTTP/1.1\r\n",
+            ),
+            (
+                "/search?",
+                "GET /search?q=a%20b%26c%3Dd&lang=fr&name=Zo%C3%AB~ HTTP/1.1\r\n",
+            ),
+        ];
+        for (path, line) in cases {
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 64];
+            let request = Request::get().path(path).query(&params).build();
+            block_on(client.request(request, &mut rx_buf)).unwrap();
+            assert!(conn.written().starts_with(line.as_bytes()), "{}", path);
+        }
+
+        // The parameters follow the query of a URL
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "example.com");
+        let mut rx_buf = [0; 64];
+        let url = crate::url::Url::parse("http://example.com?v=1").unwrap();
+        let request = Request::get().url(&url).query(&params[1..2]).build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert!(conn.written().starts_with(b"GET /?v=1&lang=fr HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn test_basic_auth_padding() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let request = Request::get().basic_auth("user", "pass").build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert!(contains(conn.written(), b"Authorization: Basic dXNlcjpwYXNz\r\n"));
+    }
+
+    #[test]
+    fn test_read_body_until_close() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.0 200 OK\r\n\r\nhello world", &mut tx).with_read_chunk_size(4);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello world"[..]), response.payload);
+
+        // The body fills the buffer exactly
+        let rx = b"HTTP/1.1 200 OK\r\nConnection: close\r\n\r\nhello world";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 49];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello world"[..]), response.payload);
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 48];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(error, Some(Error::BufferTooSmall)));
+        assert_eq!(Some(&b"hello worl"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_response_buffer() {
+        /// A circular buffer, into which the start of the response was received.
+        struct Ring {
+            storage: [u8; 64],
+            start: usize,
+            len: usize,
+        }
+
+        impl ResponseBuffer for Ring {
+            fn contiguous(&mut self) -> (&mut [u8], usize) {
+                self.storage.rotate_left(self.start);
+                self.start = 0;
+                (&mut self.storage, self.len)
+            }
+        }
+
+        // The data wraps around the end of the ring, and the rest comes from the connection
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut ring = Ring {
+            storage: [0; 64],
+            start: 50,
+            len: 30,
+        };
+        for (i, b) in rx[..30].iter().enumerate() {
+            ring.storage[(50 + i) % 64] = *b;
+        }
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&rx[30..], &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let response = block_on(client.request(Request::get().build(), &mut ring)).unwrap();
+        assert_eq!(Some("5"), response.header("content-length"));
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_request_buffered() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(40);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_buffer_strategy::<SplitBuffers>();
+        let mut header_buf = [0; 40];
+        let mut body_buf = [0; 11];
+        let response =
+            block_on(client.request_buffered(Request::get().build(), (&mut header_buf, &mut body_buf))).unwrap();
+        assert_eq!(Some(&b"hello world"[..]), response.payload);
+        assert_eq!(Some("11"), response.header("content-length"));
+
+        // Data of the next response received along with the header is kept for the next request
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nokHTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\nyes";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(48);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_buffer_strategy::<SplitBuffers>();
+        let mut header_buf = [0; 64];
+        let mut body_buf = [0; 4];
+        let response =
+            block_on(client.request_buffered(Request::get().build(), (&mut header_buf, &mut body_buf))).unwrap();
+        assert_eq!(Some(&b"ok"[..]), response.payload);
+        let response =
+            block_on(client.request_buffered(Request::get().build(), (&mut header_buf, &mut body_buf))).unwrap();
+        assert_eq!(Some(&b"yes"[..]), response.payload);
+
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_buffer_strategy::<SplitBuffers>();
+        let mut header_buf = [0; 128];
+        let mut body_buf = [0; 8];
+        let result = block_on(client.request_buffered(Request::get().build(), (&mut header_buf, &mut body_buf)));
+        assert!(matches!(result, Err(Error::BufferTooSmall)));
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request_buffered(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_request_body_with_length() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut body = block_on(client.request_body(Request::put().build(), Some(10))).unwrap();
+        block_on(body.write(b"01234")).unwrap();
+        block_on(body.write(b"56789")).unwrap();
+        block_on(body.finish()).unwrap();
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.response(&mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"ok"[..]), response.payload);
+        assert!(conn.written().ends_with(b"Content-Length: 10\r\n\r\n0123456789"));
+    }
+
+    #[test]
+    fn test_request_with_body_reader() {
+        let mut image = [0; 1000];
+        for (i, b) in image.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+        let mut tx = [0; 1100];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 201 Created\r\nContent-Length: 0\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        // The reader holds more than the body, which is not read
+        let mut body = MockNetwork::new(&image, &mut []).with_read_chunk_size(100);
+        let mut rx_buf = [0; 64];
+        let request = Request::put().path("/firmware").build();
+        let response = block_on(client.request_with_body_reader(request, &mut body, 600, &mut rx_buf)).unwrap();
+        assert_eq!(Status::Created, response.status);
+        let written = conn.written();
+        assert!(contains(written, b"Content-Length: 600\r\n\r\n"));
+        assert!(written.ends_with(&image[..600]));
+        let mut rest = [0; 8];
+        assert_eq!(8, block_on(body.read(&mut rest)).unwrap());
+        assert_eq!(&image[600..608], &rest);
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut body = MockNetwork::new(b"short", &mut []);
+        let result = block_on(client.request_with_body_reader(Request::put().build(), &mut body, 6, &mut rx_buf));
+        assert!(matches!(result, Err(Error::BodyLengthMismatch)));
+    }
+
+    #[test]
+    fn test_request_body_length_mismatch() {
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(b"", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+
+        let mut body = block_on(client.request_body(Request::put().build(), Some(4))).unwrap();
+        block_on(body.write(b"012")).unwrap();
+        assert!(matches!(block_on(body.finish()), Err(Error::BodyLengthMismatch)));
+
+        let mut body = block_on(client.request_body(Request::put().build(), Some(4))).unwrap();
+        assert!(matches!(block_on(body.write(b"01234")), Err(Error::BodyLengthMismatch)));
+    }
+
+    #[test]
+    fn test_body_length_beyond_address_space() {
+        // Lengths beyond 4 GiB, as would not fit in the usize of small targets
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        assert!(block_on(client.request_body(Request::put().build(), Some(5_000_000_000))).is_ok());
+        assert!(contains(conn.written(), b"Content-Length: 5000000000\r\n"));
+
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5000000000\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(error, Some(Error::BufferTooSmall)));
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_response_too_large() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(38);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 48];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::BufferTooSmall)));
+        // Reading stops with the body bytes received along with the header
+        assert_eq!(2, block_on(conn.read(&mut [0; 16])).unwrap());
+
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 32];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::BufferTooSmall)));
+    }
+
+    #[test]
+    fn test_back_to_back_requests() {
+        // Both responses arrive in the read for the first one
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nworld\r\n0\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_error_after(rx.len());
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"world"[..]), response.payload);
+
+        // The rest of the data is kept by the client, whichever buffer is given next
+        let mut other_buf = [0; 256];
+        let response = block_on(client.request(Request::get().build(), &mut other_buf)).unwrap();
+        assert_eq!(Status::NoContent, response.status);
+
+        // More data than the client keeps cannot be dropped without losing track of the responses
+        let mut rx = [b'x'; 256];
+        rx[..43].copy_from_slice(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello");
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(&rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::ConnectionClosed)));
+    }
+
+    #[test]
+    fn test_request_streaming() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 40\r\n\r\n0123456789abcdefghijklmnopqrstuvwxyzABCDnext";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(16);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        // Too small to hold the whole body
+        let mut rx_buf = [0; 48];
+        let (response, mut reader) = block_on(client.request_streaming(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some("40"), response.header("content-length"));
+
+        let mut body = std::vec::Vec::new();
+        let mut buf = [0; 7];
+        loop {
+            let n = block_on(reader.read(&mut buf)).unwrap();
+            if n == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(&b"0123456789abcdefghijklmnopqrstuvwxyzABCD"[..], body);
+    }
+
+    #[test]
+    fn test_streamed_body_keep_alive() {
+        for rx in [
+            &b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 204 No Content\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n",
+        ] {
+            // The data of the next response is kept once the body is read
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 128];
+            {
+                let (_, mut reader) =
+                    block_on(client.request_streaming(Request::get().build(), &mut rx_buf)).unwrap();
+                let mut body = [0; 8];
+                assert_eq!(5, block_on(reader.read(&mut body)).unwrap());
+                assert_eq!(0, block_on(reader.read(&mut body)).unwrap());
+            }
+            let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+            assert_eq!(Status::NoContent, response.status);
+
+            // The rest of a body left unread is still on the connection
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            {
+                let (_, mut reader) =
+                    block_on(client.request_streaming(Request::get().build(), &mut rx_buf)).unwrap();
+                assert_eq!(2, block_on(reader.read(&mut [0; 2])).unwrap());
+            }
+            let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+            assert!(matches!(result, Err(Error::ConnectionClosed)));
+
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut source = MockNetwork::new(b"", &mut []);
+            let mut sink_buf = [0; 16];
+            let mut sink = MockNetwork::new(b"", &mut sink_buf);
+            let mut tx_buf = [0; 8];
+            let request = Request::post().build();
+            block_on(client.pump(request, &mut source, None, &mut tx_buf, &mut sink, &mut rx_buf)).unwrap();
+            assert_eq!(b"hello", sink.written());
+            let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+            assert_eq!(Status::NoContent, response.status);
+
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut page = [0; 4];
+            let mut pages = std::vec::Vec::new();
+            let request = Request::get().build();
+            block_on(client.request_aligned(request, &mut rx_buf, &mut page, |p| pages.push(p.to_vec()))).unwrap();
+            assert_eq!(b"hello", &pages.concat()[..]);
+            let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+            assert_eq!(Status::NoContent, response.status);
+        }
+    }
+
+    #[test]
+    fn test_chunked_response() {
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n5\r\n{\"a\":\r\n9;x=y\r\n \"0\r\n\r\n\"}\r\n0\r\n\r\n";
+        for chunk_size in [1, 7, rx.len()] {
+            let mut tx = [0; 128];
+            let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(chunk_size);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 128];
+            let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+            assert_eq!(Status::Ok, response.status);
+            assert_eq!(Some(&b"{\"a\": \"0\r\n\r\n\"}"[..]), response.payload);
+        }
+    }
+
+    #[test]
+    fn test_chunked_response_errors() {
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nfoo\r\nbar\r\n0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::Codec)));
+
+        // The buffer has room for the header and part of the body only
+        let rx =
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n20\r\n0123456789abcdefghijklmnopqrstuv\r\n0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(error, Some(Error::BufferTooSmall)));
+        assert_eq!(Some(&b"0123456789abcdefg"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_transfer_encoding_chain() {
+        let mut gzip = false;
+        assert!(matches!(
+            parse_transfer_encoding(" identity , chunked", false, &mut gzip),
+            Ok(true)
+        ));
+        assert!(matches!(
+            parse_transfer_encoding("identity", false, &mut gzip),
+            Ok(false)
+        ));
+        assert!(matches!(parse_transfer_encoding("", true, &mut gzip), Ok(true)));
+        assert!(!gzip);
+        assert!(matches!(
+            parse_transfer_encoding("gzip, chunked", false, &mut gzip),
+            Ok(true)
+        ));
+        assert!(gzip);
+
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: identity\r\nTransfer-Encoding: Chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        for rx in [
+            &b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked, identity\r\n\r\n0\r\n\r\n"[..],
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: compress, chunked\r\n\r\n0\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, gzip, chunked\r\n\r\n0\r\n\r\n",
+        ] {
+            let mut tx = [0; 128];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+            assert!(matches!(result, Err(Error::InvalidTransferEncoding)));
+        }
+    }
+
+    /// `hello hello hello`, compressed with gzip.
+    const GZIP_HELLO: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xcb\x48\xcd\xc9\xc9\x57\xc8\x40\x90\x00\x80\x88\xf9\xe5\x11\x00\x00\x00";
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_response() {
+        let mut rx = [0; 256];
+        let header = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 28\r\n\r\n";
+        let next = b"HTTP/1.1 204 No Content\r\n\r\n";
+        rx[..header.len()].copy_from_slice(header);
+        rx[header.len()..header.len() + 28].copy_from_slice(GZIP_HELLO);
+        rx[header.len() + 28..header.len() + 28 + next.len()].copy_from_slice(next);
+        let rx = &rx[..header.len() + 28 + next.len()];
+
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello hello hello"[..]), response.payload);
+        // The data of the next response is kept
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::NoContent, response.status);
+
+        // The decoded body is located after the encoded one when kept across attempts
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let response =
+            block_on(client.request_with_retry(Request::get().build(), &mut rx_buf, &|| 0, |_| false)).unwrap();
+        assert_eq!(Some(&b"hello hello hello"[..]), response.payload);
+
+        // The decoded body must fit after the encoded one
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 100];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::BufferTooSmall)));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_gzip_transfer_coding() {
+        let mut rx = [0; 256];
+        let header = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip, chunked\r\n\r\n10\r\n";
+        let mut len = 0;
+        for part in [
+            &header[..],
+            &GZIP_HELLO[..16],
+            b"\r\nc\r\n",
+            &GZIP_HELLO[16..],
+            b"\r\n0\r\n\r\n",
+        ] {
+            rx[len..len + part.len()].copy_from_slice(part);
+            len += part.len();
+        }
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(&rx[..len], &mut tx).with_read_chunk_size(5);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello hello hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_gzip_not_asked_for() {
+        let mut rx = [0; 128];
+        let header = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 28\r\n\r\n";
+        rx[..header.len()].copy_from_slice(header);
+        rx[header.len()..header.len() + 28].copy_from_slice(GZIP_HELLO);
+        let rx = &rx[..header.len() + 28];
+
+        // The body is returned as received, such as a stored `.gz` file
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::get().no_accept_encoding().build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Some(GZIP_HELLO), response.payload);
+
+        // A streamed body is never asked to be compressed
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let (_, mut reader) = block_on(client.request_streaming(Request::get().build(), &mut rx_buf)).unwrap();
+        let mut body = [0; 64];
+        let mut len = 0;
+        loop {
+            let n = block_on(reader.read(&mut body[len..])).unwrap();
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        assert_eq!(GZIP_HELLO, &body[..len]);
+        assert!(!contains(conn.written(), b"Accept-Encoding"));
+
+        // A transfer coding cannot be kept, so the body cannot be streamed
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\nContent-Length: 0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let result = block_on(client.request_streaming(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::UnsupportedEncoding)));
+    }
+
+    #[cfg(not(feature = "gzip"))]
+    #[test]
+    fn test_gzip_unsupported() {
+        let mut rx = [0; 128];
+        let header = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: gzip\r\nContent-Length: 28\r\n\r\n";
+        rx[..header.len()].copy_from_slice(header);
+        rx[header.len()..header.len() + 28].copy_from_slice(GZIP_HELLO);
+        let rx = &rx[..header.len() + 28];
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(error, Some(Error::UnsupportedEncoding)));
+        assert_eq!(Some(GZIP_HELLO), response.payload);
+    }
+
+    #[test]
+    fn test_lenient_chunk_end() {
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::BodyLengthMismatch)));
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_lenient_chunk_end();
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        // A partial trailer is dropped as well, also when streaming the body
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\nX-Checksum: 12";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(4);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_lenient_chunk_end();
+        let mut rx_buf = [0; 128];
+        let (_, mut reader) = block_on(client.request_streaming(Request::get().build(), &mut rx_buf)).unwrap();
+        let mut body = [0; 16];
+        let mut len = 0;
+        loop {
+            let n = block_on(reader.read(&mut body[len..])).unwrap();
+            if n == 0 {
+                break;
+            }
+            len += n;
+        }
+        assert_eq!(b"hello", &body[..len]);
+        // The end of the body is reported again by later reads
+        assert_eq!(0, block_on(reader.read(&mut body)).unwrap());
+    }
+
+    #[test]
+    fn test_response_without_headers() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx).with_read_chunk_size(1);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert!(response.content_type.is_none());
+        assert_eq!(None, response.payload);
+        assert_eq!(0, response.headers().count());
+    }
+
+    #[test]
+    fn test_trailing_data_on_closing_connection() {
+        let rx = b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 5\r\n\r\nhello garbage";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_strict_trailing_data();
+        let mut rx_buf = [0; 128];
+        let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert!(matches!(error, Some(Error::BodyLengthMismatch)));
+    }
+
+    #[test]
+    fn test_trailing_data_on_persistent_connection() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 200 OK\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_strict_trailing_data();
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_serialize_request() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&[], &mut tx);
+        let client = HttpClient::new(&mut conn, "localhost");
+        let request = Request::post()
+            .path("/data")
+            .payload(b"hello")
+            .no_accept_encoding()
+            .build();
+
+        let mut buf = [0; 128];
+        let len = client.serialize_request(&request, &mut buf).unwrap();
+        assert_eq!(
+            &b"POST /data HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello"[..],
+            &buf[..len]
+        );
+
+        let mut buf = [0; 32];
+        assert!(matches!(
+            client.serialize_request(&request, &mut buf),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn test_lenient_status_line() {
+        let rx = b"\xEF\xBB\xBF\r\n HTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-Foo: bar\r\n\r\nhello";
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(2);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_lenient_status_line();
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert_eq!(Some("bar"), response.header("x-foo"));
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::Codec)));
+    }
+
+    #[test]
+    fn test_content_type_parameters() {
+        let rx =
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/json ; charset=\"utf-8\"\r\nContent-Length: 2\r\n\r\n{}";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(response.content_type, Some(ContentType::ApplicationJson)));
+        assert_eq!(Some("utf-8"), response.charset());
+    }
+
+    #[test]
+    fn test_status_line_spacing() {
+        let rx = b"HTTP/1.0  404   Not Found\r\nContent-Length: 0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::NotFound, response.status);
+        assert_eq!("Not Found", response.reason());
+
+        for rx in [
+            &b"HTTP/1.1 20\r\n\r\n"[..],
+            b"HTTP/1.1 2x0 OK\r\n\r\n",
+            b"HTTP/1.1\r\n\r\n",
+        ] {
+            let mut tx = [0; 128];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 128];
+            let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+            assert!(matches!(result, Err(Error::Codec)));
+        }
+    }
+
+    #[test]
+    fn test_request_with_retry() {
+        let rx = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(1);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let attempts = core::cell::RefCell::new(std::vec::Vec::new());
+        let should_retry = |context: &RetryContext<'_>| {
+            attempts.borrow_mut().push(context.attempt);
+            matches!(context.result, Ok(Status::ServiceUnavailable))
+        };
+        let response =
+            block_on(client.request_with_retry(Request::get().build(), &mut rx_buf, &|| 0, should_retry)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+        assert_eq!(&[1, 2], &attempts.borrow()[..]);
+    }
+
+    #[test]
+    fn test_no_read_before_body() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&[], &mut tx).with_read_error_after(0);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::post().payload(b"hello").no_accept_encoding().build();
+        assert!(block_on(client.request(request, &mut rx_buf)).is_err());
+        // The first read fails, so the body can only have been sent if it was written before it
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nhello"[..],
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_bearer_auth() {
+        // Longer than any fixed scratch buffer
+        let token = "t".repeat(300);
+        let mut tx = [0; 512];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        block_on(client.request(Request::get().bearer_auth(&token).build(), &mut rx_buf)).unwrap();
+        let header = std::format!("\r\nAuthorization: Bearer {}\r\n", token);
+        assert!(contains(conn.written(), header.as_bytes()));
+
+        let mut tx = [0; 512];
+        let mut conn = MockNetwork::new(b"", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 64];
+        let request = Request::get().bearer_auth("abc\r\nX-Injected: 1").build();
+        let result = block_on(client.request(request, &mut rx_buf));
+        assert!(matches!(result, Err(Error::InvalidHeader)));
+        assert!(conn.written().is_empty());
+    }
+
+    #[test]
+    fn test_expect_continue() {
+        let rx = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::post()
+            .payload(b"hello")
+            .expect_continue()
+            .no_accept_encoding()
+            .build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"ok"[..]), response.payload);
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\nhello"[..],
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_expect_continue_rejected() {
+        let rx = b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 2\r\n\r\nno";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::post().payload(b"hello").expect_continue().build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::Unauthorized, response.status);
+        assert_eq!(Some(&b"no"[..]), response.payload);
+
+        // The announced body was never sent, so the connection cannot be reused
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::ConnectionClosed)));
+        assert!(!contains(conn.written(), b"hello"));
+    }
+
+    #[test]
+    fn test_expect_continue_timeout() {
+        /// A connection on which the server only answers once it has received the body.
+        struct Hesitant<'a>(MockNetwork<'a>);
+
+        impl Io for Hesitant<'_> {
+            type Error = MockError;
+        }
+
+        impl Read for Hesitant<'_> {
+            type ReadFuture<'a> = impl Future<Output = Result<usize, MockError>> + 'a where Self: 'a;
+
+            fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+                async move {
+                    if !self.0.written().ends_with(b"hello") {
+                        core::future::pending::<()>().await;
+                    }
+                    self.0.read(buf).await
+                }
+            }
+        }
+
+        impl Write for Hesitant<'_> {
+            type WriteFuture<'a> = <MockNetwork<'a> as Write>::WriteFuture<'a> where Self: 'a;
+            type FlushFuture<'a> = <MockNetwork<'a> as Write>::FlushFuture<'a> where Self: 'a;
+
+            fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+                self.0.write(buf)
+            }
+
+            fn flush(&mut self) -> Self::FlushFuture<'_> {
+                self.0.flush()
+            }
+        }
+
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok";
+        let mut tx = [0; 128];
+        let mut conn = Hesitant(MockNetwork::new(rx, &mut tx));
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::post().payload(b"hello").expect_continue().build();
+        let response = block_on(client.request_with_continue_timeout(request, &mut rx_buf, ready(()))).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"ok"[..]), response.payload);
+        assert!(conn.0.written().ends_with(b"Expect: 100-continue\r\n\r\nhello"));
+
+        // A server answering in time still gets to reject the body
+        let rx = b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 2\r\n\r\nno";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let request = Request::post().payload(b"hello").expect_continue().build();
+        let response = block_on(client.request_with_continue_timeout(request, &mut rx_buf, ready(()))).unwrap();
+        assert_eq!(Status::Unauthorized, response.status);
+        assert!(!contains(conn.written(), b"hello"));
+    }
+
+    #[test]
+    fn test_expectation_failed() {
+        let rx = b"HTTP/1.1 417 Expectation Failed\r\nContent-Length: 0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::post()
+            .payload(b"hello")
+            .expect("custom-token")
+            .no_accept_encoding()
+            .build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::ExpectationFailed, response.status);
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: custom-token\r\n\r\n"[..],
+            conn.written()
+        );
+
+        // An expectation among the extra headers also holds back the payload
+        let rx = b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 204 No Content\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let headers = [("Expect", "100-continue")];
+        let request = Request::post()
+            .payload(b"hello")
+            .headers(&headers)
+            .no_accept_encoding()
+            .build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::NoContent, response.status);
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\nhello"[..],
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_accept_encoding() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&[], &mut tx);
+        let client = HttpClient::new(&mut conn, "localhost");
+        let mut buf = [0; 128];
+        let len = client.serialize_request(&Request::get().build(), &mut buf).unwrap();
+        // Only the codings the client can decode are asked for
+        assert_eq!(
+            cfg!(feature = "gzip"),
+            contains(&buf[..len], b"\r\nAccept-Encoding: gzip\r\n")
+        );
+
+        let request = Request::get().no_accept_encoding().build();
+        let len = client.serialize_request(&request, &mut buf).unwrap();
+        assert!(!contains(&buf[..len], b"Accept-Encoding"));
+    }
+
+    #[test]
+    fn test_default_headers() {
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let defaults = [
+            ("Authorization", "Bearer default"),
+            ("User-Agent", "reqwless"),
+            ("Accept", "*/*"),
+        ];
+        let mut client = HttpClient::new(&mut conn, "localhost").with_default_headers(&defaults);
+        let mut rx_buf = [0; 128];
+        let headers = [("accept", "application/json")];
+        let request = Request::get()
+            .basic_auth("user", "pass")
+            .headers(&headers)
+            .no_accept_encoding()
+            .build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(
+            &b"GET / HTTP/1.1\r\nHost: localhost\r\nAuthorization: Basic dXNlcjpwYXNz\r\nUser-Agent: reqwless\r\naccept: application/json\r\n\r\n"[..],
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_connection_close() {
+        let rx = b"HTTP/1.1 200 OK\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(4);
+        let defaults = [("Connection", "keep-alive")];
+        let mut client = HttpClient::new(&mut conn, "localhost")
+            .with_default_headers(&defaults)
+            .with_connection_close();
+        let mut rx_buf = [0; 128];
+        let request = Request::get().no_accept_encoding().build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::ConnectionClosed)));
+        assert_eq!(
+            &b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n"[..],
+            conn.written()
+        );
+    }
+
+    #[test]
+    fn test_on_header() {
+        let rx =
+            b"HTTP/1.1 103 Early Hints\r\nLink: </a>\r\n\r\nHTTP/1.1 200 OK\r\nX-A: 1\r\nContent-Length: 2\r\n\r\nok";
+        let seen = core::cell::RefCell::new(std::vec::Vec::new());
+        let on_header = |name: &str, value: &str| {
+            seen.borrow_mut().push(std::format!("{}={}", name, value));
+            true
+        };
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_on_header(&on_header);
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"ok"[..]), response.payload);
+        assert_eq!(&["X-A=1", "Content-Length=2"][..], &seen.borrow()[..]);
+
+        let reject = |name: &str, _: &str| !name.eq_ignore_ascii_case("x-a");
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_on_header(&reject);
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::HeaderRejected)));
+    }
+
+    #[test]
+    fn test_lenient_header_end() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\nhello";
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(4);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_lenient_header_end();
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some("5"), response.header("content-length"));
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::ConnectionClosed)));
+    }
+
+    #[test]
+    fn test_lenient_version_case() {
+        let rx = b"http/1.0 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_lenient_version_case();
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request(Request::get().build(), &mut rx_buf));
+        assert!(matches!(result, Err(Error::Codec)));
+    }
+
+    #[test]
+    fn test_header_like_status_line() {
+        let rx = b"HTTP/1.1 200 OK\r\nHTTP2-Settings: AAMAAABkAARAAAAAAAIAAAAA\r\nContent-Length: 5\r\n\r\nhello";
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_if_match_precondition_failed() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(
+            b"HTTP/1.1 412 Precondition Failed\r\nContent-Length: 0\r\n\r\n",
+            &mut tx,
+        );
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::put().path("/doc").if_match("\"v1\"").payload(b"new").build();
+        let response = block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::PreconditionFailed, response.status);
+        assert!(matches!(
+            response.error_for_status(),
+            Err(Error::Status(Status::PreconditionFailed))
+        ));
+        assert!(contains(conn.written(), b"\r\nIf-Match: \"v1\"\r\n"));
+    }
+
+    #[test]
+    fn test_header_injection() {
+        for headers in [
+            [("X-Name", "value\r\nX-Injected: yes")],
+            [("X-Name", "value\nX-Injected: yes")],
+            [("X-Name\r\nX-Injected", "yes")],
+            [("X Name", "value")],
+            [("", "value")],
+        ] {
+            let mut tx = [0; 128];
+            let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let mut rx_buf = [0; 128];
+            let request = Request::get().headers(&headers).build();
+            assert!(matches!(
+                block_on(client.request(request, &mut rx_buf)),
+                Err(Error::InvalidHeader)
+            ));
+            assert!(conn.written().is_empty());
+        }
+
+        assert!(validate_header("X-Name", "tab\tseparated, \"quoted\"").is_ok());
+    }
+
+    #[test]
+    fn test_raw_request_line() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let request = Request::get()
+            .path("/ignored")
+            .raw_request_line("M-SEARCH * HTTP/1.1")
+            .build();
+        block_on(client.request(request, &mut rx_buf)).unwrap();
+        assert!(conn
+            .written()
+            .starts_with(b"M-SEARCH * HTTP/1.1\r\nHost: localhost\r\n"));
+
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let request = Request::get()
+            .raw_request_line("GET / HTTP/1.1\r\nX-Injected: yes")
+            .build();
+        assert!(matches!(
+            block_on(client.request(request, &mut rx_buf)),
+            Err(Error::InvalidHeader)
+        ));
+        assert!(conn.written().is_empty());
+    }
+
+    #[test]
+    fn test_trailing_data_policy() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhelloHTTP/1.1 200 OK\r\n";
+        for (policy, rejected) in [
+            (TrailingData::Retain, false),
+            (TrailingData::RejectOnClose, false),
+            (TrailingData::Reject, true),
+        ] {
+            let mut tx = [0; 128];
+            let mut conn = MockNetwork::new(rx, &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost").with_trailing_data(policy);
+            let mut rx_buf = [0; 128];
+            let (response, error) = block_on(client.request_partial(Request::get().build(), &mut rx_buf)).unwrap();
+            assert_eq!(Some(&b"hello"[..]), response.payload);
+            assert_eq!(rejected, matches!(error, Some(Error::BodyLengthMismatch)));
+        }
+    }
+
+    #[test]
+    fn test_skip_early_hints() {
+        let rx = b"HTTP/1.1 103 Early Hints\r\nLink: </style.css>; rel=preload\r\nContent-Type: application/json\r\nContent-Length: 7\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(16);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert!(response.content_type.is_none());
+        assert_eq!(None, response.header("link"));
+        assert_eq!("HTTP/1.1 200 OK", response.status_line());
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_max_header_line() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&[], &mut tx);
+        let client = HttpClient::new(&mut conn, "localhost").with_max_header_line(18);
+        let mut buf = [0; 128];
+        let headers = [("X-Name", "1234567890")];
+        let request = Request::get().headers(&headers).no_accept_encoding().build();
+        assert!(client.serialize_request(&request, &mut buf).is_ok());
+        let headers = [("X-Name", "12345678901")];
+        let request = Request::get().headers(&headers).build();
+        assert!(matches!(
+            client.serialize_request(&request, &mut buf),
+            Err(Error::HeaderTooLong)
+        ));
+
+        // "Authorization: Basic " followed by the 4 characters encoding "a:b"
+        let request = Request::get().basic_auth("a", "b").build();
+        let client = HttpClient::new(&mut conn, "localhost").with_max_header_line(24);
+        assert!(matches!(
+            client.serialize_request(&request, &mut buf),
+            Err(Error::HeaderTooLong)
+        ));
+        let client = HttpClient::new(&mut conn, "localhost").with_max_header_line(25);
+        assert!(client.serialize_request(&request, &mut buf).is_ok());
+    }
+
+    #[test]
+    fn test_header_casing_preserved() {
+        let head =
+            "HTTP/1.1 200 OK\r\nCONTENT-type: application/json\r\ncontent-LENGTH: 2\r\nX-Request-ID: AbC\r\n\r\n";
+        let rx = std::format!("{}{{}}", head);
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx.as_bytes(), &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let response = block_on(client.request(Request::get().build(), &mut rx_buf)).unwrap();
+        assert!(matches!(response.content_type, Some(ContentType::ApplicationJson)));
+        assert_eq!(Some(&b"{}"[..]), response.payload);
+        assert_eq!(Some("AbC"), response.header("x-request-id"));
+        assert_eq!(head, response.raw_headers());
+        assert_eq!(Some(("X-Request-ID", "AbC")), response.headers().last());
+    }
+
+    #[test]
+    fn test_request_body_from_producer() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 204 No Content\r\n\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut producer_tx = [];
+        let mut producer = MockNetwork::new(b"sensordata", &mut producer_tx).with_read_chunk_size(4);
+        let mut buf = [0; 8];
+        let mut writer = block_on(client.request_body(Request::post().no_accept_encoding().build(), None)).unwrap();
+        block_on(writer.write_from(&mut producer, &mut buf)).unwrap();
+        block_on(writer.finish()).unwrap();
+        let mut rx_buf = [0; 128];
+        assert_eq!(
+            Status::NoContent,
+            block_on(client.response(&mut rx_buf)).unwrap().status
+        );
+        assert_eq!(
+            &b"POST / HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nsens\r\n4\r\norda\r\n2\r\nta\r\n0\r\n\r\n"[..],
+            conn.written()
+        );
+    }
+
+    #[cfg(feature = "content-md5")]
+    #[test]
+    fn test_content_md5() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(&[], &mut tx);
+        let client = HttpClient::new(&mut conn, "localhost");
+        let mut buf = [0; 128];
+        let request = Request::post().payload(b"hello").content_md5().build();
+        let len = client.serialize_request(&request, &mut buf).unwrap();
+        assert!(contains(&buf[..len], b"\r\nContent-MD5: XUFAKrxLKna5cZ2REBfFkg==\r\n"));
+    }
+
+    #[test]
+    fn test_connection_closed_in_header() {
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(b"HTTP/1.1 200 OK\r\n", &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        assert!(matches!(
+            block_on(client.request(Request::get().build(), &mut rx_buf)),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn test_request_with_retry_timeout() {
+        let rx = b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\nHTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_total_timeout(10);
+        let mut rx_buf = [0; 128];
+        // Each attempt takes 3 seconds, so the timeout is exceeded before a fifth attempt
+        // could be made
+        let now = core::cell::Cell::new(0);
+        let clock = || {
+            now.set(now.get() + 3);
+            now.get()
+        };
+        let result = block_on(client.request_with_retry(Request::get().build(), &mut rx_buf, &clock, |_| true));
+        assert!(matches!(result, Err(Error::Timeout)));
+    }
+
+    #[test]
+    fn test_request_with_timeout() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let deadline = core::future::pending();
+        let response = block_on(client.request_with_timeout(Request::get().build(), &mut rx_buf, deadline)).unwrap();
+        assert_eq!(Some(&b"hi"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_request_timed_out() {
+        /// A connection on which the server never answers.
+        struct Stalled;
+
+        impl Io for Stalled {
+            type Error = ErrorKind;
+        }
+
+        impl Read for Stalled {
+            type ReadFuture<'a> = core::future::Pending<Result<usize, ErrorKind>> where Self: 'a;
+
+            fn read<'a>(&'a mut self, _: &'a mut [u8]) -> Self::ReadFuture<'a> {
+                core::future::pending()
+            }
+        }
+
+        impl Write for Stalled {
+            type WriteFuture<'a> = Ready<Result<usize, ErrorKind>> where Self: 'a;
+            type FlushFuture<'a> = Ready<Result<(), ErrorKind>> where Self: 'a;
+
+            fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+                ready(Ok(buf.len()))
+            }
+
+            fn flush(&mut self) -> Self::FlushFuture<'_> {
+                ready(Ok(()))
+            }
+        }
+
+        let mut conn = Stalled;
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        let result = block_on(client.request_with_timeout(Request::get().build(), &mut rx_buf, ready(())));
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert!(matches!(
+            block_on(client.request(Request::get().build(), &mut rx_buf)),
+            Err(Error::ConnectionClosed)
+        ));
+    }
+
+    #[cfg(feature = "time")]
+    #[test]
+    fn test_request_timed() {
+        let rx = b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nbody";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(8);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 128];
+        // Each reading of the clock advances it by a millisecond more than the previous one
+        let now = core::cell::Cell::new((0, 0));
+        let clock = || {
+            let (time, step) = now.get();
+            now.set((time + step + 1, step + 1));
+            time
+        };
+        let (response, timings) = block_on(client.request_timed(Request::get().build(), &mut rx_buf, &clock)).unwrap();
+        assert_eq!(Some(&b"body"[..]), response.payload);
+        assert_eq!(
+            Timings {
+                time_to_first_byte: 1,
+                header: 2,
+                body: 3
+            },
+            timings
+        );
+    }
+
+    #[test]
+    fn test_pump_chunked_response() {
+        let rx = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\n\r\nab\r\r\n3\r\n\r\n0\r\n0\r\n\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx).with_read_chunk_size(7);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut body_tx = [];
+        let mut body = MockNetwork::new(&[], &mut body_tx);
+        let mut tx_buf = [0; 8];
+        let mut sink_buf = [0; 16];
+        let mut sink = MockNetwork::new(&[], &mut sink_buf);
+        let mut rx_buf = [0; 64];
+        let response = block_on(client.pump(
+            Request::get().build(),
+            &mut body,
+            Some(0),
+            &mut tx_buf,
+            &mut sink,
+            &mut rx_buf,
+        ))
+        .unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(b"\r\nab\r\r\n0", sink.written());
     }
 }
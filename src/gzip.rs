@@ -0,0 +1,126 @@
+//! Decoding of gzip bodies, as described in RFC 1952.
+use crate::client::Error;
+
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+
+/// Decode the gzip member `data` into `out`, returning the decoded length.
+///
+/// Fails with [`Error::BufferTooSmall`] if the decoded data does not fit in `out`, and with
+/// [`Error::Codec`] if `data` is not a valid gzip member or fails its integrity check.
+pub(crate) fn decode(data: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let start = header_len(data).ok_or_else(|| {
+        warn!("Invalid gzip header");
+        Error::Codec
+    })?;
+    // The compressed data is followed by its CRC-32 and its length modulo 2^32
+    let end = data
+        .len()
+        .checked_sub(8)
+        .filter(|end| *end >= start)
+        .ok_or(Error::Codec)?;
+    let trailer = &data[end..];
+
+    let len =
+        miniz_oxide::inflate::decompress_slice_iter_to_slice(out, core::iter::once(&data[start..end]), false, true)
+            .map_err(|status| match status {
+                miniz_oxide::inflate::TINFLStatus::HasMoreOutput => {
+                    warn!("Decoded body does not fit in {} bytes", out.len());
+                    Error::BufferTooSmall
+                }
+                _ => {
+                    warn!("Invalid gzip data: {:?}", status as i8);
+                    Error::Codec
+                }
+            })?;
+
+    let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+    if crc != crc32(&out[..len]) || size != len as u32 {
+        warn!("Decoded body of {} bytes fails the gzip integrity check", len);
+        return Err(Error::Codec);
+    }
+    Ok(len)
+}
+
+/// Return the length of the header starting `data`, if it is a valid gzip header.
+fn header_len(data: &[u8]) -> Option<usize> {
+    // Only the deflate compression method is defined
+    if data.get(..3)? != [0x1f, 0x8b, 8] {
+        return None;
+    }
+    let flags = *data.get(3)?;
+    // Skip the modification time, extra flags and operating system
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = u16::from_le_bytes([*data.get(pos)?, *data.get(pos + 1)?]);
+        pos += 2 + len as usize;
+    }
+    for flag in [FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            // The file name and comment are zero-terminated
+            pos += data.get(pos..)?.iter().position(|b| *b == 0)? + 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    Some(pos).filter(|pos| *pos <= data.len())
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for b in data {
+        crc ^= *b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `{"hello": "world", "items": [1, 2, 3, 1, 2, 3, 1, 2, 3]}`, compressed with gzip.
+    const HELLO: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xab\x56\xca\x48\xcd\xc9\xc9\x57\xb2\x52\x50\x2a\xcf\x2f\xca\x49\x51\xd2\x51\x50\xca\x2c\x49\xcd\x2d\x06\x8a\x44\x1b\xea\x28\x18\xe9\x28\x18\xeb\x28\xa0\x33\x62\x6b\x01\x19\x20\x90\xa1\x38\x00\x00\x00";
+
+    #[test]
+    fn test_decode() {
+        let mut out = [0; 64];
+        let len = decode(HELLO, &mut out).unwrap();
+        assert_eq!(
+            &b"{\"hello\": \"world\", \"items\": [1, 2, 3, 1, 2, 3, 1, 2, 3]}"[..],
+            &out[..len]
+        );
+
+        // The same data, with a file name
+        let mut named = [0; 96];
+        named[..10].copy_from_slice(&HELLO[..10]);
+        named[3] = FNAME;
+        named[10..16].copy_from_slice(b"a.txt\0");
+        named[16..16 + HELLO.len() - 10].copy_from_slice(&HELLO[10..]);
+        let len = decode(&named[..HELLO.len() + 6], &mut out).unwrap();
+        assert_eq!(56, len);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        let mut out = [0; 64];
+        assert!(matches!(decode(&HELLO[..HELLO.len() - 1], &mut out), Err(Error::Codec)));
+        assert!(matches!(decode(&HELLO[1..], &mut out), Err(Error::Codec)));
+        assert!(matches!(decode(b"\x1f\x8b\x08", &mut out), Err(Error::Codec)));
+        let mut corrupt = [0; 60];
+        corrupt.copy_from_slice(HELLO);
+        corrupt[52] ^= 1;
+        assert!(matches!(decode(&corrupt, &mut out), Err(Error::Codec)));
+        assert!(matches!(decode(HELLO, &mut out[..40]), Err(Error::BufferTooSmall)));
+    }
+}
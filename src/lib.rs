@@ -1,12 +1,52 @@
 #![no_std]
+#![feature(generic_associated_types)]
+#![feature(type_alias_impl_trait)]
 #![doc = include_str!("../README.md")]
 use embedded_io::asynch::{Read, Write};
 
 mod fmt;
 
+pub mod body;
+pub mod buffer;
+pub mod cache;
 pub mod client;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod multipart;
+pub mod redirect;
 pub mod request;
+pub mod retry;
+pub mod url;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 /// A Convenience trait for an underlying transport implemented on embedded-io.
+///
+/// Reads and writes must wait until the transport is ready rather than fail with a "would block"
+/// error, which the async traits have no way to express. The client treats a read returning 0
+/// bytes as the end of the stream, and a write accepting 0 bytes of a non-empty buffer as a closed
+/// connection, failing with [`Error::ConnectionClosed`](client::Error::ConnectionClosed) if the
+/// exchange is not complete. Any error of the transport fails the request with
+/// [`Error::Network`](client::Error::Network).
 pub trait Network: Read + Write {}
 impl<N: Read + Write> Network for N {}
+
+/// A source of the current time, in seconds unless used for request timings.
+///
+/// Only differences between returned values matter, so any monotonic time base can be used.
+/// Request timings are reported in the unit of the clock, which can then be finer than seconds.
+pub trait Clock {
+    /// Return the current time.
+    fn now(&self) -> u64;
+}
+
+impl<F> Clock for F
+where
+    F: Fn() -> u64,
+{
+    fn now(&self) -> u64 {
+        self()
+    }
+}
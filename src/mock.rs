@@ -0,0 +1,166 @@
+//! A scriptable in-memory [`Network`](crate::Network) for testing code built on the client.
+//!
+//! The mock replays a canned response and records everything written to it. It can be configured
+//! to return short reads, accept only partial writes and fail after a given number of bytes, to
+//! check that the code using it copes with the behavior of real transports.
+use core::future::{ready, Future, Ready};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use embedded_io::asynch::{Read, Write};
+use embedded_io::{ErrorKind, Io};
+
+/// The error returned by [`MockNetwork`] when a scripted failure is reached.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MockError;
+
+impl embedded_io::Error for MockError {
+    fn kind(&self) -> ErrorKind {
+        ErrorKind::Other
+    }
+}
+
+/// An in-memory connection replaying `rx` to reads and recording writes into `tx`.
+///
+/// Once `rx` is exhausted, reads signal end of stream. Writes fail once `tx` is full.
+pub struct MockNetwork<'a> {
+    rx: &'a [u8],
+    read: usize,
+    tx: &'a mut [u8],
+    written: usize,
+    largest_write: usize,
+    read_chunk_size: Option<usize>,
+    write_chunk_size: Option<usize>,
+    read_error_after: Option<usize>,
+    write_error_after: Option<usize>,
+}
+
+impl<'a> MockNetwork<'a> {
+    /// Create a new mock connection replaying `rx` and recording writes into `tx`.
+    pub fn new(rx: &'a [u8], tx: &'a mut [u8]) -> Self {
+        Self {
+            rx,
+            read: 0,
+            tx,
+            written: 0,
+            largest_write: 0,
+            read_chunk_size: None,
+            write_chunk_size: None,
+            read_error_after: None,
+            write_error_after: None,
+        }
+    }
+
+    /// Return at most `chunk_size` bytes per read.
+    pub fn with_read_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        self.read_chunk_size.replace(chunk_size);
+        self
+    }
+
+    /// Accept at most `chunk_size` bytes per write, returning a partial write for larger buffers.
+    pub fn with_write_chunk_size(mut self, chunk_size: usize) -> Self {
+        assert!(chunk_size > 0);
+        self.write_chunk_size.replace(chunk_size);
+        self
+    }
+
+    /// Fail reads once `len` bytes have been read.
+    pub fn with_read_error_after(mut self, len: usize) -> Self {
+        self.read_error_after.replace(len);
+        self
+    }
+
+    /// Fail writes once `len` bytes have been written.
+    pub fn with_write_error_after(mut self, len: usize) -> Self {
+        self.write_error_after.replace(len);
+        self
+    }
+
+    /// The bytes written to the connection so far.
+    pub fn written(&self) -> &[u8] {
+        &self.tx[..self.written]
+    }
+
+    /// The size of the largest buffer passed to a single write.
+    pub fn largest_write(&self) -> usize {
+        self.largest_write
+    }
+
+    fn do_read(&mut self, buf: &mut [u8]) -> Result<usize, MockError> {
+        let mut len = core::cmp::min(buf.len(), self.rx.len() - self.read);
+        if let Some(chunk_size) = self.read_chunk_size {
+            len = core::cmp::min(len, chunk_size);
+        }
+        if let Some(limit) = self.read_error_after {
+            if self.read >= limit {
+                return Err(MockError);
+            }
+            len = core::cmp::min(len, limit - self.read);
+        }
+        buf[..len].copy_from_slice(&self.rx[self.read..self.read + len]);
+        self.read += len;
+        Ok(len)
+    }
+
+    fn do_write(&mut self, buf: &[u8]) -> Result<usize, MockError> {
+        self.largest_write = core::cmp::max(self.largest_write, buf.len());
+        let mut len = buf.len();
+        if let Some(chunk_size) = self.write_chunk_size {
+            len = core::cmp::min(len, chunk_size);
+        }
+        if let Some(limit) = self.write_error_after {
+            if self.written >= limit {
+                return Err(MockError);
+            }
+            len = core::cmp::min(len, limit - self.written);
+        }
+        if self.written + len > self.tx.len() {
+            return Err(MockError);
+        }
+        self.tx[self.written..self.written + len].copy_from_slice(&buf[..len]);
+        self.written += len;
+        Ok(len)
+    }
+}
+
+impl Io for MockNetwork<'_> {
+    type Error = MockError;
+}
+
+impl Read for MockNetwork<'_> {
+    type ReadFuture<'a> = Ready<Result<usize, MockError>> where Self: 'a;
+
+    fn read<'a>(&'a mut self, buf: &'a mut [u8]) -> Self::ReadFuture<'a> {
+        ready(self.do_read(buf))
+    }
+}
+
+impl Write for MockNetwork<'_> {
+    type WriteFuture<'a> = Ready<Result<usize, MockError>> where Self: 'a;
+    type FlushFuture<'a> = Ready<Result<(), MockError>> where Self: 'a;
+
+    fn write<'a>(&'a mut self, buf: &'a [u8]) -> Self::WriteFuture<'a> {
+        ready(self.do_write(buf))
+    }
+
+    fn flush(&mut self) -> Self::FlushFuture<'_> {
+        ready(Ok(()))
+    }
+}
+
+/// Run a future to completion on the current thread.
+///
+/// The future is polled in a busy loop, which suits futures driven by a [`MockNetwork`] as they
+/// never wait.
+pub fn block_on<F: Future>(mut f: F) -> F::Output {
+    let waker = crate::client::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    // Safety: the future is shadowed and never moved again
+    let mut f = unsafe { Pin::new_unchecked(&mut f) };
+    loop {
+        if let Poll::Ready(output) = f.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Reading of multipart response bodies, such as `multipart/byteranges` responses to range
+//! requests.
+use crate::client::{find_sequence, Error};
+use crate::request::ContentRange;
+use embedded_io::asynch::Read;
+use embedded_io::Error as _;
+
+/// A reader splitting a multipart body into its parts.
+///
+/// The body is read from `source` through `buf`, which must be large enough to hold the header of
+/// each part. Parts are visited in order with [`next_part`](Self::next_part), and the body of the
+/// current part is read with [`read`](Self::read).
+pub struct MultipartReader<'b, R>
+where
+    R: Read,
+{
+    source: R,
+    boundary: &'b [u8],
+    buf: &'b mut [u8],
+    pos: usize,
+    len: usize,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    /// Skipping the data preceding the first part.
+    Preamble,
+    /// Following a delimiter, before the header of a part or the end of the body.
+    Delimiter,
+    /// Reading the body of a part.
+    Body,
+    /// Past the final delimiter.
+    Done,
+}
+
+impl<'b, R> MultipartReader<'b, R>
+where
+    R: Read,
+{
+    /// Create a reader for a multipart body read from `source`, with parts separated by `boundary`.
+    ///
+    /// The boundary is found in the `Content-Type` of the response, see
+    /// [`Response::multipart_boundary`](crate::request::Response::multipart_boundary). Fails with
+    /// [`Error::BufferTooSmall`] if `buf` cannot hold a delimiter line.
+    pub fn new(source: R, boundary: &'b str, buf: &'b mut [u8]) -> Result<Self, Error> {
+        if buf.len() < boundary.len() + 8 {
+            warn!("Buffer too small for multipart boundary");
+            return Err(Error::BufferTooSmall);
+        }
+        // The first delimiter is not preceded by a line break, so pretend it is
+        buf[..2].copy_from_slice(b"\r\n");
+        Ok(Self {
+            source,
+            boundary: boundary.as_bytes(),
+            buf,
+            pos: 0,
+            len: 2,
+            state: State::Preamble,
+        })
+    }
+
+    /// Advance to the next part, skipping what is left of the current one, and return its header.
+    ///
+    /// Returns `None` once the final delimiter is reached.
+    pub async fn next_part(&mut self) -> Result<Option<PartHeaders<'_>>, Error> {
+        while matches!(self.state, State::Preamble | State::Body) {
+            match self.find_delimiter() {
+                Some(i) => {
+                    self.pos = i + self.delimiter_len();
+                    self.state = State::Delimiter;
+                }
+                None => {
+                    self.pos = self.safe_end();
+                    self.fill().await?;
+                }
+            }
+        }
+
+        while self.state == State::Delimiter {
+            if self.buf[self.pos..self.len].starts_with(b"--") {
+                self.state = State::Done;
+            } else if let Some(end) = find_sequence(&self.buf[self.pos..self.len], b"\r\n\r\n") {
+                // Skip the rest of the delimiter line, which may hold padding
+                let header = &self.buf[self.pos..self.pos + end + 2];
+                let start = find_sequence(header, b"\r\n").unwrap_or(0) + 2;
+                let raw = core::str::from_utf8(&header[start..])?;
+                self.pos += end + 4;
+                self.state = State::Body;
+                return Ok(Some(PartHeaders { raw }));
+            } else {
+                self.fill().await?;
+            }
+        }
+        Ok(None)
+    }
+
+    /// Advance to the next part of a `multipart/byteranges` body like
+    /// [`next_part`](Self::next_part), and return the range of the resource it holds.
+    ///
+    /// The range is always [`ContentRange::Bytes`]. Fails with [`Error::Codec`] if the part has no
+    /// valid `Content-Range` header holding a range.
+    pub async fn next_range(&mut self) -> Result<Option<ContentRange>, Error> {
+        match self.next_part().await? {
+            Some(part) => match part.content_range() {
+                Some(range @ ContentRange::Bytes { .. }) => Ok(Some(range)),
+                _ => {
+                    warn!("Multipart byte range has no valid content range");
+                    Err(Error::Codec)
+                }
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Read a part of the body of the current part into `dst`.
+    ///
+    /// Returns 0 at the end of the part.
+    pub async fn read(&mut self, dst: &mut [u8]) -> Result<usize, Error> {
+        while self.state == State::Body {
+            let delimiter = self.find_delimiter();
+            if delimiter == Some(self.pos) {
+                self.pos += self.delimiter_len();
+                self.state = State::Delimiter;
+                break;
+            }
+            let end = delimiter.unwrap_or_else(|| self.safe_end());
+            if end > self.pos {
+                let n = core::cmp::min(dst.len(), end - self.pos);
+                dst[..n].copy_from_slice(&self.buf[self.pos..self.pos + n]);
+                self.pos += n;
+                return Ok(n);
+            }
+            self.fill().await?;
+        }
+        Ok(0)
+    }
+
+    fn delimiter_len(&self) -> usize {
+        b"\r\n--".len() + self.boundary.len()
+    }
+
+    /// Find the next delimiter in the buffered data.
+    fn find_delimiter(&self) -> Option<usize> {
+        let len = self.delimiter_len();
+        if self.len < self.pos + len {
+            return None;
+        }
+        (self.pos..=self.len - len).find(|i| {
+            let candidate = &self.buf[*i..*i + len];
+            candidate.starts_with(b"\r\n--") && &candidate[4..] == self.boundary
+        })
+    }
+
+    /// The end of the buffered data that cannot be part of a delimiter.
+    fn safe_end(&self) -> usize {
+        core::cmp::max(self.pos, self.len.saturating_sub(self.delimiter_len() - 1))
+    }
+
+    /// Read more data from the source, after moving the unconsumed data to the start of `buf`.
+    async fn fill(&mut self) -> Result<(), Error> {
+        self.buf.copy_within(self.pos..self.len, 0);
+        self.len -= self.pos;
+        self.pos = 0;
+        if self.len == self.buf.len() {
+            warn!("Multipart header does not fit in {} bytes", self.buf.len());
+            return Err(Error::BufferTooSmall);
+        }
+        let n = self
+            .source
+            .read(&mut self.buf[self.len..])
+            .await
+            .map_err(|e| e.kind())?;
+        if n == 0 {
+            warn!("Multipart body ended before the final delimiter");
+            return Err(Error::Codec);
+        }
+        self.len += n;
+        Ok(())
+    }
+}
+
+/// The header of a part of a multipart body.
+#[derive(Debug)]
+pub struct PartHeaders<'a> {
+    raw: &'a str,
+}
+
+impl<'a> PartHeaders<'a> {
+    /// Iterate over the name and value of each header of the part, in the order received.
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.raw
+            .split("\r\n")
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+    }
+
+    /// Return the value of the first header named `name`, compared case-insensitively.
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Return the range of the resource held by the part, from its `Content-Range` header.
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self.get("content-range").and_then(ContentRange::parse)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use crate::client::HttpClient;
+    use crate::mock::{block_on, MockNetwork};
+    use crate::request::{ByteRange, Request, Status};
+
+    fn read_part<R: Read>(reader: &mut MultipartReader<'_, R>) -> std::vec::Vec<u8> {
+        let mut body = std::vec::Vec::new();
+        let mut chunk = [0; 4];
+        loop {
+            let n = block_on(reader.read(&mut chunk)).unwrap();
+            if n == 0 {
+                return body;
+            }
+            body.extend_from_slice(&chunk[..n]);
+        }
+    }
+
+    #[test]
+    fn test_multipart() {
+        let body = b"preamble\r\n--XYZ\r\nContent-Range: bytes 0-4/10\r\n\r\nhello\r\n--XYZ  \r\n\r\nwor\r\nld\r\n--XYZ\r\nContent-Type: text/plain\r\n\r\nskipped\r\n--XYZ--\r\n";
+        let mut tx = [];
+        let mut buf = [0; 48];
+        let mut reader =
+            MultipartReader::new(MockNetwork::new(body, &mut tx).with_read_chunk_size(3), "XYZ", &mut buf).unwrap();
+
+        let part = block_on(reader.next_part()).unwrap().unwrap();
+        assert_eq!(Some("bytes 0-4/10"), part.get("content-range"));
+        assert_eq!(b"hello", &read_part(&mut reader)[..]);
+
+        let part = block_on(reader.next_part()).unwrap().unwrap();
+        assert_eq!(0, part.iter().count());
+        assert_eq!(b"wor\r\nld", &read_part(&mut reader)[..]);
+
+        let part = block_on(reader.next_part()).unwrap().unwrap();
+        assert_eq!(Some("text/plain"), part.get("Content-Type"));
+        assert!(block_on(reader.next_part()).unwrap().is_none());
+        assert_eq!(0, block_on(reader.read(&mut [0; 4])).unwrap());
+    }
+
+    #[test]
+    fn test_multiple_ranges() {
+        let rx = b"HTTP/1.1 206 Partial Content\r\nContent-Type: multipart/byteranges; boundary=B\r\nContent-Length: 119\r\n\r\n\
+            --B\r\nContent-Range: bytes 0-4/11\r\n\r\nhello\r\n\
+            --B\r\nContent-Type: text/plain\r\nContent-Range: bytes 6-10/*\r\n\r\nworld\r\n\
+            --B--\r\n";
+        let mut tx = [0; 128];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let ranges = [ByteRange::FromTo(0, 4), ByteRange::From(6)];
+        let request = Request::get().path("/file").ranges(&ranges).build();
+        let mut rx_buf = [0; 256];
+        let (response, body) = block_on(client.request_streaming(request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::PartialContent, response.status);
+
+        let mut buf = [0; 64];
+        let mut reader = MultipartReader::new(body, response.multipart_boundary().unwrap(), &mut buf).unwrap();
+        let range = block_on(reader.next_range()).unwrap();
+        let expected = ContentRange::Bytes {
+            start: 0,
+            end: 4,
+            total: Some(11),
+        };
+        assert_eq!(Some(expected), range);
+        assert_eq!(b"hello", &read_part(&mut reader)[..]);
+        let range = block_on(reader.next_range()).unwrap();
+        let expected = ContentRange::Bytes {
+            start: 6,
+            end: 10,
+            total: None,
+        };
+        assert_eq!(Some(expected), range);
+        assert_eq!(b"world", &read_part(&mut reader)[..]);
+        assert!(block_on(reader.next_range()).unwrap().is_none());
+
+        assert!(conn.written().starts_with(b"GET /file HTTP/1.1\r\n"));
+        let range = b"\r\nRange: bytes=0-4,6-\r\n";
+        assert!(conn.written().windows(range.len()).any(|w| w == range));
+    }
+
+    #[test]
+    fn test_multipart_unterminated() {
+        let body = b"--XYZ\r\n\r\nhello";
+        let mut tx = [];
+        let mut buf = [0; 32];
+        let mut reader = MultipartReader::new(MockNetwork::new(body, &mut tx), "XYZ", &mut buf).unwrap();
+        assert!(block_on(reader.next_part()).unwrap().is_some());
+        assert!(matches!(block_on(reader.next_part()), Err(Error::Codec)));
+    }
+
+    #[test]
+    fn test_multipart_buffer_too_small() {
+        let mut tx = [];
+        let mut buf = [0; 10];
+        let result = MultipartReader::new(MockNetwork::new(b"", &mut tx), "XYZ", &mut buf);
+        assert!(matches!(result, Err(Error::BufferTooSmall)));
+    }
+}
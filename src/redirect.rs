@@ -0,0 +1,814 @@
+//! Following redirects, on the same connection or to other hosts with a new connection for each
+//! request.
+use crate::buffer::BufferStrategy;
+use crate::client::{layout_of, response_at, Error, HttpClient};
+use crate::request::*;
+use crate::url::{split_authority, Url, UrlScheme};
+use crate::{Clock, Network};
+use core::future::Future;
+
+/// A way to open connections to arbitrary hosts, used to follow redirects leaving the host.
+pub trait Connector {
+    /// The connection type opened by the connector.
+    type Connection: Network;
+    /// The future returned by [`connect`](Self::connect).
+    type ConnectFuture<'a>: Future<Output = Result<Self::Connection, Error>>
+    where
+        Self: 'a;
+
+    /// Open a connection to `port` on `host`, establishing TLS over it for the `https` scheme.
+    ///
+    /// A connector should fail for a scheme it does not support, such as `https` without TLS,
+    /// rather than fall back to another one.
+    fn connect<'a>(&'a mut self, scheme: UrlScheme, host: &'a str, port: u16) -> Self::ConnectFuture<'a>;
+}
+
+/// A HTTP client following redirects to any host, connecting to it with a [`Connector`].
+///
+/// The host and path of the current request are kept in a buffer provided when creating the
+/// follower, which must be large enough to hold those of every location followed.
+pub struct RedirectFollower<'a, C>
+where
+    C: Connector,
+{
+    connector: &'a mut C,
+    url_buf: &'a mut [u8],
+    max_redirects: usize,
+    is_done: fn(&Status) -> bool,
+    total_timeout: Option<(u64, &'a dyn Clock)>,
+}
+
+impl<'a, C> RedirectFollower<'a, C>
+where
+    C: Connector,
+{
+    /// Create a new redirect follower opening connections with `connector`, and using `url_buf`
+    /// to hold the host and path of the current request.
+    pub fn new(connector: &'a mut C, url_buf: &'a mut [u8]) -> Self {
+        Self {
+            connector,
+            url_buf,
+            max_redirects: 5,
+            is_done: |_| true,
+            total_timeout: None,
+        }
+    }
+
+    /// Limit the time spent on all hops of a request to `seconds`, as measured by `clock`.
+    ///
+    /// Once exceeded, the request fails with [`Error::Timeout`] instead of following the next
+    /// redirect. The limit is checked between hops, so a hop already running is not cut short.
+    pub fn with_total_timeout(mut self, seconds: u64, clock: &'a dyn Clock) -> Self {
+        self.total_timeout.replace((seconds, clock));
+        self
+    }
+
+    /// Follow at most `max_redirects` redirects for a request. Defaults to 5.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Only accept responses whose status satisfies `is_done` as the end of a request.
+    ///
+    /// By default, any status other than a redirect ends the request. With for example
+    /// `with_stop_condition(Status::is_success)`, redirects are followed until a 2xx response,
+    /// and any other final response fails the request with [`Error::Status`].
+    pub fn with_stop_condition(mut self, is_done: fn(&Status) -> bool) -> Self {
+        self.is_done = is_done;
+        self
+    }
+
+    /// Perform a HTTP request to `port` on `host` with `scheme`, following the redirects of the
+    /// responses.
+    ///
+    /// Every request, including the first one, is made on a new connection opened with the
+    /// connector. The request is sent again as is to each location, except that a
+    /// `303 See Other`, or a `301` or `302` redirect of a POST request, is followed with a GET
+    /// request without a body. Once the location has another scheme, host or port than the first
+    /// request, the credentials of the request are no longer sent: its authentication and the
+    /// `Authorization`, `Proxy-Authorization` and `Cookie` headers among its extra headers.
+    ///
+    /// Fails with [`Error::TooManyRedirects`] if the response is still a redirect once the maximum
+    /// number of redirects were followed. Other responses end the request if they satisfy the
+    /// [stop condition](Self::with_stop_condition).
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request<'m>(
+        &mut self,
+        scheme: UrlScheme,
+        host: &str,
+        port: u16,
+        request: Request<'m>,
+        rx_buf: &'m mut [u8],
+    ) -> Result<Response<'m>, Error> {
+        let mut request = request;
+        let (path, query) = (request.path.unwrap_or("/"), request.raw_query);
+        let mut target = Target::new(self.url_buf, scheme, host, port, path, query)?;
+        let start = self.total_timeout.map(|(timeout, clock)| (timeout, clock, clock.now()));
+        let base = rx_buf.as_ptr();
+        let mut redirects = 0;
+        let layout = loop {
+            // Only the location of the response in rx_buf is kept, as the client borrows url_buf
+            let layout = {
+                let (authority, path) = target.get(self.url_buf);
+                let host = target.host(authority);
+                let mut connection = self.connector.connect(target.scheme, host, target.port).await?;
+                let mut client = HttpClient::new(&mut connection, authority);
+                let mut hop = request.clone();
+                hop.path = Some(path);
+                hop.raw_query = None;
+                let response = client.request(hop, rx_buf).await?;
+                layout_of(&response, base)
+            };
+
+            let response = response_at(rx_buf, layout)?;
+            let location = match response.redirect_location() {
+                Some(_) if redirects == self.max_redirects => {
+                    warn!("Giving up after {} redirects", redirects);
+                    return Err(Error::TooManyRedirects);
+                }
+                Some(location) => location,
+                None if (self.is_done)(&response.status) => break layout,
+                None => {
+                    warn!("Redirects ended with {:?}", response.status);
+                    return Err(Error::Status(response.status));
+                }
+            };
+            if let Some((timeout, clock, start)) = start {
+                if clock.now() - start >= timeout {
+                    warn!(
+                        "Giving up after {} redirects, exceeding the timeout of {}s",
+                        redirects, timeout
+                    );
+                    return Err(Error::Timeout);
+                }
+            }
+            trace!("Following redirect to {}", location);
+            if target.update(self.url_buf, location)? && !request.omit_credentials {
+                trace!("Redirect leaves the origin, dropping credentials");
+                request.auth = None;
+                request.omit_credentials = true;
+            }
+            // The location holds the query to send, if any
+            request.query = None;
+            redirect_method(&mut request, response.status);
+            redirects += 1;
+        };
+        response_at(rx_buf, layout)
+    }
+}
+
+impl<'a, N, B> HttpClient<'a, N, B>
+where
+    N: Network + 'a,
+    B: BufferStrategy,
+{
+    /// Perform a HTTP request like [`request`](HttpClient::request), following the redirects of the
+    /// responses to other locations on the same origin.
+    ///
+    /// Each redirect is followed on the same connection, with the host and path of the current
+    /// request kept in `url_buf`, which must be large enough to hold those of every location
+    /// followed. A `303 See Other`, or a `301` or `302` redirect of a POST request, is followed
+    /// with a GET request without a body, while other redirects send the request again as is.
+    ///
+    /// The connection is taken to use `scheme` to the host and port the client was created for. As
+    /// the client is bound to it, a location with another scheme, host or port fails with
+    /// [`Error::Codec`]; use a [`RedirectFollower`] to follow those. Fails with
+    /// [`Error::TooManyRedirects`] if the response is still a redirect once `max_redirects`
+    /// redirects were followed, and with [`Error::Timeout`] if the
+    /// [total timeout](HttpClient::with_total_timeout) of the client, measured with `clock`, is
+    /// exceeded before following a redirect.
+    ///
+    /// The returned response references data in the provided `rx_buf` argument.
+    pub async fn request_following_redirects<'m, C>(
+        &mut self,
+        scheme: UrlScheme,
+        request: Request<'_>,
+        rx_buf: &'m mut [u8],
+        url_buf: &mut [u8],
+        max_redirects: u8,
+        clock: &C,
+    ) -> Result<Response<'m>, Error>
+    where
+        C: Clock,
+    {
+        let mut request = request;
+        let (path, query) = (request.path.unwrap_or("/"), request.raw_query);
+        let (_, port) = split_authority(self.host, scheme)?;
+        let mut target = Target::new(url_buf, scheme, self.host, port, path, query)?;
+        let start = clock.now();
+        let base = rx_buf.as_ptr();
+        let mut redirects = 0;
+        let layout = loop {
+            let layout = {
+                let mut hop = request.clone();
+                hop.path = Some(target.get(url_buf).1);
+                hop.raw_query = None;
+                let response = self.request(hop, rx_buf).await?;
+                layout_of(&response, base)
+            };
+
+            let response = response_at(rx_buf, layout)?;
+            let location = match response.redirect_location() {
+                Some(_) if redirects == max_redirects => {
+                    warn!("Giving up after {} redirects", redirects);
+                    return Err(Error::TooManyRedirects);
+                }
+                Some(location) => location,
+                None => break layout,
+            };
+            if let Some(timeout) = self.total_timeout.filter(|t| clock.now() - start >= *t) {
+                warn!(
+                    "Giving up after {} redirects, exceeding the timeout of {}s",
+                    redirects, timeout
+                );
+                return Err(Error::Timeout);
+            }
+            trace!("Following redirect to {}", location);
+            if target.update(url_buf, location)? {
+                warn!("Redirect leaves the origin of the connection: {}", location);
+                return Err(Error::Codec);
+            }
+            request.query = None;
+            redirect_method(&mut request, response.status);
+            redirects += 1;
+        };
+        response_at(rx_buf, layout)
+    }
+}
+
+/// Turn `request` into a GET request without a body if it is redirected with `status` that asks
+/// for it: a `303 See Other`, or a `301` or `302` redirect of a POST request, as browsers do.
+fn redirect_method(request: &mut Request<'_>, status: Status) {
+    let to_get = match status {
+        Status::SeeOther => true,
+        Status::MovedPermanently | Status::Found => matches!(request.method, Method::POST),
+        _ => false,
+    };
+    if to_get {
+        request.method = Method::GET;
+        request.payload = None;
+        request.content_type = None;
+    }
+}
+
+/// The authority and path of the current request, stored one after the other in the url buffer.
+struct Target {
+    authority_len: usize,
+    path_len: usize,
+    scheme: UrlScheme,
+    port: u16,
+}
+
+impl Target {
+    fn new(
+        buf: &mut [u8],
+        scheme: UrlScheme,
+        authority: &str,
+        port: u16,
+        path: &str,
+        query: Option<&str>,
+    ) -> Result<Self, Error> {
+        let mut target = Self {
+            authority_len: 0,
+            path_len: 0,
+            scheme,
+            port,
+        };
+        target.set_authority(buf, authority)?;
+        target.set_path(buf, path)?;
+        if let Some(query) = query {
+            target.append_query(buf, query)?;
+        }
+        Ok(target)
+    }
+
+    fn get<'b>(&self, buf: &'b [u8]) -> (&'b str, &'b str) {
+        let (authority, path) = buf.split_at(self.authority_len);
+        // Both were copied from strings
+        (
+            core::str::from_utf8(authority).unwrap_or(""),
+            core::str::from_utf8(&path[..self.path_len]).unwrap_or("/"),
+        )
+    }
+
+    /// Return the host part of `authority`, without the port.
+    fn host<'b>(&self, authority: &'b str) -> &'b str {
+        match authority.rsplit_once(':') {
+            Some((host, port)) if !port.contains(']') => host,
+            _ => authority,
+        }
+    }
+
+    /// Move to the target of a redirect `location`, which may be relative to the current path.
+    ///
+    /// Returns whether the location has another scheme, host or port than the current target.
+    fn update(&mut self, buf: &mut [u8], location: &str) -> Result<bool, Error> {
+        // The fragment is not sent to the server
+        let location = location.split('#').next().unwrap_or(location);
+        let first_segment = location.split(|c| c == '/' || c == '?').next().unwrap_or("");
+        if location.starts_with("//") {
+            warn!("Unsupported redirect location: {}", location);
+            return Err(Error::Codec);
+        } else if !first_segment.contains(':') {
+            // Stay on the same host
+            self.resolve(buf, location)?;
+            return Ok(false);
+        }
+
+        let url = Url::parse(location)?;
+        let host = self.host(core::str::from_utf8(&buf[..self.authority_len]).unwrap_or(""));
+        let other_origin =
+            url.scheme() != self.scheme || !url.host().eq_ignore_ascii_case(host) || url.port() != self.port;
+        self.scheme = url.scheme();
+        self.port = url.port();
+        self.set_authority(buf, url.authority())?;
+        self.set_path(buf, "/")?;
+        self.resolve(buf, url.path())?;
+        if let Some(query) = url.query() {
+            self.append_query(buf, query)?;
+        }
+        Ok(other_origin)
+    }
+
+    /// Resolve the relative `reference` against the current path, as described in RFC 3986.
+    ///
+    /// The path of the reference is merged with the directory of the current path, unless it is
+    /// absolute, and its `.` and `..` segments are removed. A reference with only a query keeps
+    /// the current path, and an empty reference keeps the current path and query.
+    fn resolve(&mut self, buf: &mut [u8], reference: &str) -> Result<(), Error> {
+        let (path, query) = match reference.find('?') {
+            Some(i) => (&reference[..i], Some(&reference[i..])),
+            None => (reference, None),
+        };
+        let start = self.authority_len;
+        let current = &buf[start..start + self.path_len];
+        let current_len = current.iter().position(|b| *b == b'?').unwrap_or(current.len());
+
+        let mut len = if path.is_empty() {
+            if query.is_none() {
+                return Ok(());
+            }
+            current_len
+        } else {
+            let keep = if path.starts_with('/') {
+                0
+            } else {
+                // A relative path replaces the last segment of the current path
+                match current[..current_len].iter().rposition(|b| *b == b'/') {
+                    Some(i) => i + 1,
+                    None => {
+                        *buf.get_mut(start).ok_or(Error::BufferTooSmall)? = b'/';
+                        1
+                    }
+                }
+            };
+            let end = start + keep + path.len();
+            buf.get_mut(start + keep..end)
+                .ok_or(Error::BufferTooSmall)?
+                .copy_from_slice(path.as_bytes());
+            remove_dot_segments(&mut buf[start..end])
+        };
+        if let Some(query) = query {
+            buf.get_mut(start + len..start + len + query.len())
+                .ok_or(Error::BufferTooSmall)?
+                .copy_from_slice(query.as_bytes());
+            len += query.len();
+        }
+        self.path_len = len;
+        Ok(())
+    }
+
+    fn set_authority(&mut self, buf: &mut [u8], authority: &str) -> Result<(), Error> {
+        buf.get_mut(..authority.len())
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(authority.as_bytes());
+        self.authority_len = authority.len();
+        Ok(())
+    }
+
+    /// Add `query` to the current path, which has none.
+    fn append_query(&mut self, buf: &mut [u8], query: &str) -> Result<(), Error> {
+        let start = self.authority_len + self.path_len;
+        let query_buf = buf
+            .get_mut(start..start + 1 + query.len())
+            .ok_or(Error::BufferTooSmall)?;
+        query_buf[0] = b'?';
+        query_buf[1..].copy_from_slice(query.as_bytes());
+        self.path_len += 1 + query.len();
+        Ok(())
+    }
+
+    fn set_path(&mut self, buf: &mut [u8], path: &str) -> Result<(), Error> {
+        buf.get_mut(self.authority_len..self.authority_len + path.len())
+            .ok_or(Error::BufferTooSmall)?
+            .copy_from_slice(path.as_bytes());
+        self.path_len = path.len();
+        Ok(())
+    }
+}
+
+/// Remove the `.` and `..` segments of the absolute `path` in place, returning its new length.
+fn remove_dot_segments(path: &mut [u8]) -> usize {
+    let mut out = 0;
+    let mut pos = 0;
+    while pos < path.len() {
+        // Each segment starts with the slash at `pos`
+        let end = path[pos + 1..]
+            .iter()
+            .position(|b| *b == b'/')
+            .map_or(path.len(), |i| pos + 1 + i);
+        let last = end == path.len();
+        match &path[pos + 1..end] {
+            b"." => {}
+            b".." => out = path[..out].iter().rposition(|b| *b == b'/').unwrap_or(0),
+            _ => {
+                path.copy_within(pos..end, out);
+                out += end - pos;
+                pos = end;
+                continue;
+            }
+        }
+        // A trailing dot segment leaves the path ending with a slash
+        if last {
+            path[out] = b'/';
+            out += 1;
+        }
+        pos = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    extern crate std;
+    use crate::mock::{block_on, MockNetwork};
+    use core::future::{ready, Ready};
+    use std::string::String;
+    use std::vec::Vec;
+
+    /// Connects to hosts in the order of a script of canned responses, recording the requests made.
+    struct MockConnector {
+        script: Vec<(UrlScheme, &'static str, u16, &'static [u8])>,
+        connections: Vec<(String, u16, *const u8)>,
+    }
+
+    impl Connector for MockConnector {
+        type Connection = MockNetwork<'static>;
+        type ConnectFuture<'a> = Ready<Result<MockNetwork<'static>, Error>>;
+
+        fn connect<'a>(&'a mut self, scheme: UrlScheme, host: &'a str, port: u16) -> Self::ConnectFuture<'a> {
+            let (expected_scheme, expected_host, expected_port, rx) = self.script.remove(0);
+            assert_eq!((expected_scheme, expected_host, expected_port), (scheme, host, port));
+            let tx: &'static mut [u8] = std::boxed::Box::leak(std::vec![0; TX_LEN].into_boxed_slice());
+            self.connections.push((host.into(), port, tx.as_ptr()));
+            ready(Ok(MockNetwork::new(rx, tx)))
+        }
+    }
+
+    const TX_LEN: usize = 256;
+
+    /// Return the data written to a connection, once it is no longer used.
+    fn sent(tx: *const u8) -> &'static str {
+        let tx = unsafe { core::slice::from_raw_parts(tx, TX_LEN) };
+        let end = tx.iter().position(|b| *b == 0).unwrap_or(tx.len());
+        core::str::from_utf8(&tx[..end]).unwrap()
+    }
+
+    /// Whether `data` was written to the connection exactly once.
+    fn sent_once(written: &[u8], data: &[u8]) -> bool {
+        written.windows(data.len()).filter(|w| *w == data).count() == 1
+    }
+
+    #[test]
+    fn test_resolve_relative_location() {
+        let cases = [
+            ("../other", "a.example", "/a/other"),
+            ("sub/page", "a.example", "/a/b/sub/page"),
+            ("./", "a.example", "/a/b/"),
+            (".", "a.example", "/a/b/"),
+            ("..", "a.example", "/a/"),
+            ("../../../../x", "a.example", "/x"),
+            ("/x/./y/../z", "a.example", "/x/z"),
+            ("g?x=1#top", "a.example", "/a/b/g?x=1"),
+            ("?y", "a.example", "/a/b/c?y"),
+            ("#top", "a.example", "/a/b/c?q"),
+            ("", "a.example", "/a/b/c?q"),
+            ("http://b.example:81/p/../q", "b.example:81", "/q"),
+            ("https://b.example?x", "b.example", "/?x"),
+            ("HTTP://b.example/p?y/z", "b.example", "/p?y/z"),
+        ];
+        for (location, authority, path) in cases {
+            let mut buf = [0; 64];
+            let mut target = Target::new(&mut buf, UrlScheme::HTTP, "a.example", 80, "/a/b/c", Some("q")).unwrap();
+            target.update(&mut buf, location).unwrap();
+            assert_eq!((authority, path), target.get(&buf), "{}", location);
+        }
+
+        // Whether each location leaves the origin of the request
+        for (location, other_origin) in [
+            ("/x", false),
+            ("http://A.EXAMPLE:80/x", false),
+            ("https://a.example/x", true),
+            ("http://a.example:8080/x", true),
+            ("http://b.example/x", true),
+        ] {
+            let mut buf = [0; 64];
+            let mut target = Target::new(&mut buf, UrlScheme::HTTP, "a.example", 80, "/", None).unwrap();
+            assert_eq!(other_origin, target.update(&mut buf, location).unwrap(), "{}", location);
+        }
+
+        let mut buf = [0; 64];
+        let mut target = Target::new(&mut buf, UrlScheme::HTTP, "a.example", 80, "/", None).unwrap();
+        assert!(matches!(target.update(&mut buf, "ftp://b.example/"), Err(Error::Codec)));
+        assert!(matches!(target.update(&mut buf, "//b.example/"), Err(Error::Codec)));
+    }
+
+    #[test]
+    fn test_follow_redirect_to_other_host() {
+        let mut connector = MockConnector {
+            script: std::vec![
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    b"HTTP/1.1 307 Temporary Redirect\r\nLocation: /a2\r\nContent-Length: 0\r\n\r\n"
+                ),
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    b"HTTP/1.1 302 Found\r\nLocation: https://b.example/b?x=1#top\r\nContent-Length: 0\r\n\r\n"
+                ),
+                (
+                    UrlScheme::HTTPS,
+                    "b.example",
+                    443,
+                    b"HTTP/1.1 303 See Other\r\nLocation: http://c.example:8080\r\nContent-Length: 0\r\n\r\n"
+                ),
+                (
+                    UrlScheme::HTTP,
+                    "c.example",
+                    8080,
+                    b"HTTP/1.1 301 Moved Permanently\r\nLocation: ../c\r\nContent-Length: 0\r\n\r\n"
+                ),
+                (
+                    UrlScheme::HTTP,
+                    "c.example",
+                    8080,
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+                ),
+            ],
+            connections: Vec::new(),
+        };
+        let mut url_buf = [0; 64];
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf);
+        let headers = [("Cookie", "session=1"), ("X-Trace", "abc")];
+        let request = Request::post()
+            .path("/a")
+            .basic_auth("user", "pass")
+            .headers(&headers)
+            .payload(b"data")
+            .build();
+        let mut rx_buf = [0; 256];
+        let response = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        let connections = &connector.connections;
+        assert_eq!(5, connections.len());
+        assert!(sent(connections[0].2).starts_with("POST /a HTTP/1.1\r\nHost: a.example\r\n"));
+        // The credentials are sent again on the same origin only
+        for (i, connection) in connections.iter().enumerate() {
+            let sent = sent(connection.2);
+            assert_eq!(i < 2, sent.contains("Authorization: Basic"), "{}", sent);
+            assert_eq!(i < 2, sent.contains("Cookie: session=1"), "{}", sent);
+            assert!(sent.contains("X-Trace: abc"), "{}", sent);
+        }
+        assert!(sent(connections[1].2).starts_with("POST /a2 HTTP/1.1\r\nHost: a.example\r\n"));
+        assert!(sent(connections[1].2).ends_with("\r\n\r\ndata"));
+        // A POST request is redirected with a GET request by a 302 response
+        assert!(sent(connections[2].2).starts_with("GET /b?x=1 HTTP/1.1\r\nHost: b.example\r\n"));
+        assert!(sent(connections[2].2).ends_with("\r\n\r\n"));
+        assert!(sent(connections[3].2).starts_with("GET / HTTP/1.1\r\nHost: c.example:8080\r\n"));
+        assert_eq!(("c.example", 8080), (connections[4].0.as_str(), connections[4].1));
+        assert!(connector.script.is_empty());
+        assert!(sent(connections[4].2).starts_with("GET /c HTTP/1.1\r\nHost: c.example:8080\r\n"));
+    }
+
+    #[test]
+    fn test_stop_condition() {
+        let script = || {
+            std::vec![
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    &b"HTTP/1.1 302 Found\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n"[..]
+                ),
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    b"HTTP/1.1 401 Unauthorized\r\nContent-Length: 0\r\n\r\n"
+                ),
+            ]
+        };
+        let mut connector = MockConnector {
+            script: script(),
+            connections: Vec::new(),
+        };
+        let mut url_buf = [0; 64];
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf);
+        let mut rx_buf = [0; 256];
+        let request = Request::get().build();
+        let response = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf)).unwrap();
+        assert_eq!(Status::Unauthorized, response.status);
+
+        let mut connector = MockConnector {
+            script: script(),
+            connections: Vec::new(),
+        };
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf).with_stop_condition(Status::is_success);
+        let request = Request::get().build();
+        let result = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf));
+        assert!(matches!(result, Err(Error::Status(Status::Unauthorized))));
+
+        // The redirect is not a final response, whatever the stop condition
+        let mut connector = MockConnector {
+            script: script(),
+            connections: Vec::new(),
+        };
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf).with_max_redirects(0);
+        let request = Request::get().build();
+        let result = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf));
+        assert!(matches!(result, Err(Error::TooManyRedirects)));
+    }
+
+    #[test]
+    fn test_follow_redirect_on_same_host() {
+        let rx = b"HTTP/1.1 307 Temporary Redirect\r\nLocation: b\r\nContent-Length: 0\r\n\r\n\
+            HTTP/1.1 302 Found\r\nLocation: http://LOCALHOST/c\r\nContent-Length: 0\r\n\r\n\
+            HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello";
+        let mut tx = [0; 512];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let request = Request::post().path("/x/a").payload(b"data").build();
+        let mut rx_buf = [0; 256];
+        let mut url_buf = [0; 32];
+        let response =
+            block_on(client.request_following_redirects(UrlScheme::HTTP, request, &mut rx_buf, &mut url_buf, 2, &|| 0))
+                .unwrap();
+        assert_eq!(Status::Ok, response.status);
+        assert_eq!(Some(&b"hello"[..]), response.payload);
+
+        let sent = core::str::from_utf8(conn.written()).unwrap();
+        // Each request ends with its body, if any
+        let parts: Vec<&str> = sent.split("\r\n\r\n").collect();
+        assert!(parts[0].starts_with("POST /x/a HTTP/1.1\r\n"));
+        assert!(parts[1].starts_with("dataPOST /x/b HTTP/1.1\r\n"));
+        assert!(parts[2].starts_with("dataGET /c HTTP/1.1\r\n"));
+        assert_eq!(&[""], &parts[3..]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn test_follow_redirect_to_gzip_body() {
+        // `hello hello hello`, compressed with gzip
+        const GZIP_HELLO: &[u8] = b"\x1f\x8b\x08\x00\x00\x00\x00\x00\x02\x03\xcb\x48\xcd\xc9\xc9\x57\xc8\x40\x90\x00\x80\x88\xf9\xe5\x11\x00\x00\x00";
+        let redirect = b"HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n";
+        let header = b"HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: 28\r\n\r\n";
+        let body: &'static [u8] = [&header[..], GZIP_HELLO].concat().leak();
+
+        let rx = [&redirect[..], body].concat();
+        let mut tx = [0; 512];
+        let mut conn = MockNetwork::new(&rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let mut url_buf = [0; 32];
+        let request = Request::get().path("/a").build();
+        let response =
+            block_on(client.request_following_redirects(UrlScheme::HTTP, request, &mut rx_buf, &mut url_buf, 2, &|| 0))
+                .unwrap();
+        assert_eq!(Some(&b"hello hello hello"[..]), response.payload);
+
+        let mut connector = MockConnector {
+            script: std::vec![
+                (UrlScheme::HTTP, "a.example", 80, &redirect[..]),
+                (UrlScheme::HTTP, "a.example", 80, body),
+            ],
+            connections: Vec::new(),
+        };
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf);
+        let request = Request::get().build();
+        let response = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf)).unwrap();
+        assert_eq!(Some(&b"hello hello hello"[..]), response.payload);
+    }
+
+    #[test]
+    fn test_redirect_limits() {
+        let rx = b"HTTP/1.1 301 Moved Permanently\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n\
+            HTTP/1.1 301 Moved Permanently\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let mut rx_buf = [0; 256];
+        let mut url_buf = [0; 32];
+        let request = Request::get().path("/a").build();
+        let result =
+            block_on(client.request_following_redirects(UrlScheme::HTTP, request, &mut rx_buf, &mut url_buf, 1, &|| 0));
+        assert!(matches!(result, Err(Error::TooManyRedirects)));
+
+        // Locations with another scheme, host or port cannot be followed on the connection
+        for location in [
+            "http://other.example/",
+            "https://localhost/a",
+            "http://localhost:8080/a",
+        ] {
+            let rx = std::format!(
+                "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\n\r\n",
+                location
+            );
+            let mut tx = [0; 256];
+            let mut conn = MockNetwork::new(rx.as_bytes(), &mut tx);
+            let mut client = HttpClient::new(&mut conn, "localhost");
+            let request = Request::get().path("/a").basic_auth("user", "pass").build();
+            let result = block_on(client.request_following_redirects(
+                UrlScheme::HTTP,
+                request,
+                &mut rx_buf,
+                &mut url_buf,
+                1,
+                &|| 0,
+            ));
+            assert!(matches!(result, Err(Error::Codec)), "{}", location);
+            // The credentials are not sent again
+            assert!(sent_once(conn.written(), b"Authorization: Basic"));
+        }
+        // The scheme and port of the connection are the origin of relative and absolute locations
+        let rx = b"HTTP/1.1 302 Found\r\nLocation: https://localhost:8443/b\r\nContent-Length: 0\r\n\r\n\
+            HTTP/1.1 204 No Content\r\n\r\n";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost:8443");
+        let request = Request::get().path("/a").build();
+        let response = block_on(client.request_following_redirects(
+            UrlScheme::HTTPS,
+            request,
+            &mut rx_buf,
+            &mut url_buf,
+            1,
+            &|| 0,
+        ))
+        .unwrap();
+        assert_eq!(Status::NoContent, response.status);
+
+        // Each hop takes 3 seconds, so the timeout is exceeded before the second redirect
+        let rx = b"HTTP/1.1 301 Moved Permanently\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n\
+            HTTP/1.1 301 Moved Permanently\r\nLocation: /a\r\nContent-Length: 0\r\n\r\n";
+        let now = core::cell::Cell::new(0);
+        let clock = || {
+            now.set(now.get() + 3);
+            now.get()
+        };
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost").with_total_timeout(5);
+        let request = Request::get().path("/a").build();
+        let result = block_on(client.request_following_redirects(
+            UrlScheme::HTTP,
+            request,
+            &mut rx_buf,
+            &mut url_buf,
+            5,
+            &clock,
+        ));
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert_eq!(9, now.get());
+
+        let mut connector = MockConnector {
+            script: std::vec![
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    &b"HTTP/1.1 302 Found\r\nLocation: /b\r\nContent-Length: 0\r\n\r\n"[..]
+                ),
+                (
+                    UrlScheme::HTTP,
+                    "a.example",
+                    80,
+                    b"HTTP/1.1 302 Found\r\nLocation: /c\r\nContent-Length: 0\r\n\r\n"
+                ),
+            ],
+            connections: Vec::new(),
+        };
+        let mut follower = RedirectFollower::new(&mut connector, &mut url_buf).with_total_timeout(5, &clock);
+        let request = Request::get().build();
+        let result = block_on(follower.request(UrlScheme::HTTP, "a.example", 80, request, &mut rx_buf));
+        assert!(matches!(result, Err(Error::Timeout)));
+        assert!(connector.script.is_empty());
+    }
+}
@@ -1,11 +1,28 @@
+use crate::client::Error;
+use crate::url::Url;
+
 /// A read only HTTP request type
+#[derive(Clone)]
 pub struct Request<'a> {
     pub(crate) method: Method,
     pub(crate) path: Option<&'a str>,
+    pub(crate) query: Option<&'a [(&'a str, &'a str)]>,
+    /// The query of a URL the request was made for, sent as is after the path.
+    pub(crate) raw_query: Option<&'a str>,
+    pub(crate) raw_request_line: Option<&'a str>,
     pub(crate) auth: Option<Auth<'a>>,
     pub(crate) payload: Option<&'a [u8]>,
     pub(crate) content_type: Option<ContentType>,
     pub(crate) extra_headers: Option<&'a [(&'a str, &'a str)]>,
+    /// Whether to leave out the credentials among the extra headers, once redirected to another
+    /// origin than the one they were meant for.
+    pub(crate) omit_credentials: bool,
+    pub(crate) accept_encoding: bool,
+    pub(crate) expect: Option<&'a str>,
+    pub(crate) if_match: Option<&'a str>,
+    pub(crate) ranges: Option<&'a [ByteRange]>,
+    #[cfg(feature = "content-md5")]
+    pub(crate) content_md5: bool,
 }
 
 impl<'a> Default for Request<'a> {
@@ -13,10 +30,20 @@ impl<'a> Default for Request<'a> {
         Self {
             method: Method::GET,
             path: None,
+            query: None,
+            raw_query: None,
+            raw_request_line: None,
             auth: None,
             payload: None,
             content_type: None,
             extra_headers: None,
+            omit_credentials: false,
+            accept_encoding: true,
+            expect: None,
+            if_match: None,
+            ranges: None,
+            #[cfg(feature = "content-md5")]
+            content_md5: false,
         }
     }
 }
@@ -27,8 +54,37 @@ pub struct RequestBuilder<'a> {
 }
 
 /// Request authentication scheme.
+#[derive(Clone)]
 pub enum Auth<'a> {
     Basic { username: &'a str, password: &'a str },
+    Bearer { token: &'a str },
+}
+
+/// A range of bytes of a resource, requested with the `Range` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ByteRange {
+    /// The bytes from the first offset to the last offset, inclusive.
+    FromTo(u64, u64),
+    /// The bytes from the offset to the end of the resource.
+    From(u64),
+    /// The given number of bytes at the end of the resource.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Encode the range as in the `Range` header.
+    pub(crate) fn encode(&self) -> heapless::String<41> {
+        use core::fmt::Write as _;
+        // The longest range is two 20 digit offsets separated by a dash
+        let mut s = heapless::String::new();
+        let _ = match self {
+            ByteRange::FromTo(first, last) => write!(s, "{}-{}", first, last),
+            ByteRange::From(first) => write!(s, "{}-", first),
+            ByteRange::Last(len) => write!(s, "-{}", len),
+        };
+        s
+    }
 }
 
 impl<'a> Request<'a> {
@@ -71,6 +127,20 @@ impl<'a> Request<'a> {
             },
         }
     }
+
+    /// Return the value of the first extra header named `name`, compared case-insensitively.
+    pub(crate) fn header(&self, name: &str) -> Option<&'a str> {
+        self.extra_headers
+            .unwrap_or(&[])
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+
+    /// Return the expectation of the request, set with the builder or as an extra header.
+    pub(crate) fn expectation(&self) -> Option<&'a str> {
+        self.expect.or_else(|| self.header("expect"))
+    }
 }
 
 impl<'a> RequestBuilder<'a> {
@@ -86,6 +156,35 @@ impl<'a> RequestBuilder<'a> {
         self
     }
 
+    /// Append the query parameters `params` to the path of the HTTP request, as `?k1=v1&k2=v2`.
+    ///
+    /// Names and values are percent-encoded as they are sent, leaving only unreserved characters
+    /// as is. The parameters follow a query already in the path, if any.
+    pub fn query(mut self, params: &'a [(&'a str, &'a str)]) -> Self {
+        self.request.query.replace(params);
+        self
+    }
+
+    /// Set the path and query of the HTTP request to those of `url`.
+    ///
+    /// The request must be made with a client for the [authority](Url::authority) of the URL.
+    /// Query parameters added with [`query`](Self::query) follow the query of the URL.
+    pub fn url(mut self, url: &Url<'a>) -> Self {
+        self.request.raw_query = url.query();
+        self.path(url.path())
+    }
+
+    /// Send `line` as is as the request line, in place of the method, path, query and version.
+    ///
+    /// This is meant for testing servers and for protocols framed like HTTP with other request
+    /// lines. Nothing about the line is checked, except that it holds no line break, so the
+    /// request is only as valid as the line: the method and path set on the request are still used
+    /// for everything else, such as redirects and caching. The line break is added after it.
+    pub fn raw_request_line(mut self, line: &'a str) -> Self {
+        self.request.raw_request_line.replace(line);
+        self
+    }
+
     /// Set the payload to send in the HTTP request body.
     pub fn payload(mut self, payload: &'a [u8]) -> Self {
         self.request.payload.replace(payload);
@@ -104,6 +203,78 @@ impl<'a> RequestBuilder<'a> {
         self
     }
 
+    /// Set bearer token authorization for the request, sent as is in the `Authorization` header.
+    pub fn bearer_auth(mut self, token: &'a str) -> Self {
+        self.request.auth.replace(Auth::Bearer { token });
+        self
+    }
+
+    /// Do not send the `Accept-Encoding` header the client adds for the content codings it can
+    /// decode, for endpoints where an encoded body is not wanted.
+    ///
+    /// The header is sent with the `gzip` feature, asking for bodies compressed with gzip.
+    pub fn no_accept_encoding(mut self) -> Self {
+        self.request.accept_encoding = false;
+        self
+    }
+
+    /// Only apply the request if the resource still has the entity tag `etag`, by sending the
+    /// `If-Match` header.
+    ///
+    /// If the resource has changed, the server answers with [`Status::PreconditionFailed`]. `etag`
+    /// is sent as is, so it must include its quotes.
+    pub fn if_match(mut self, etag: &'a str) -> Self {
+        self.request.if_match.replace(etag);
+        self
+    }
+
+    /// Send the `Content-MD5` header, holding the MD5 digest of the payload, as required by some
+    /// older APIs.
+    ///
+    /// The header is only sent for requests with a payload.
+    #[cfg(feature = "content-md5")]
+    pub fn content_md5(mut self) -> Self {
+        self.request.content_md5 = true;
+        self
+    }
+
+    /// Send the `Expect: 100-continue` header, and only send the payload once the server has
+    /// answered with `100 Continue`.
+    ///
+    /// If the server answers with a final response instead, the payload is not sent and that
+    /// response is returned, after which the connection is spent: further requests fail with
+    /// [`Error::ConnectionClosed`]. Without this, the payload is sent immediately after the header.
+    ///
+    /// Servers that do not support expectations may never answer before receiving the payload, so
+    /// such requests are best sent with
+    /// [`request_with_continue_timeout`](crate::client::HttpClient::request_with_continue_timeout).
+    pub fn expect_continue(self) -> Self {
+        self.expect("100-continue")
+    }
+
+    /// Send the `Expect` header with the given expectation, and only send the payload once the
+    /// server has answered with `100 Continue`.
+    ///
+    /// A server that cannot meet the expectation answers with a final response such as
+    /// `417 Expectation Failed` instead, in which case the payload is not sent and that response
+    /// is returned. An `Expect` header among the extra headers is handled the same way.
+    pub fn expect(mut self, expectation: &'a str) -> Self {
+        self.request.expect.replace(expectation);
+        self
+    }
+
+    /// Only request the given `ranges` of the resource, by sending the `Range` header.
+    ///
+    /// A server supporting ranges answers with [`Status::PartialContent`]. When several ranges
+    /// are requested, the body is usually a `multipart/byteranges` body holding one part for each
+    /// range, which can be read with a
+    /// [`MultipartReader`](crate::multipart::MultipartReader). No header is sent if `ranges` is
+    /// empty.
+    pub fn ranges(mut self, ranges: &'a [ByteRange]) -> Self {
+        self.request.ranges.replace(ranges);
+        self
+    }
+
     /// Return an immutable request.
     pub fn build(self) -> Request<'a> {
         self.request
@@ -111,6 +282,8 @@ impl<'a> RequestBuilder<'a> {
 }
 
 /// HTTP request methods
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Method {
     /// GET
     GET,
@@ -132,6 +305,27 @@ impl Method {
             Method::DELETE => "DELETE",
         }
     }
+
+    /// Parse a method name, which is case-sensitive.
+    fn parse(name: &str) -> Option<Method> {
+        match name {
+            "POST" => Some(Method::POST),
+            "PUT" => Some(Method::PUT),
+            "GET" => Some(Method::GET),
+            "DELETE" => Some(Method::DELETE),
+            _ => None,
+        }
+    }
+}
+
+/// A method listed in the `Allow` header of a response.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AllowedMethod<'a> {
+    /// A method the client can send.
+    Known(Method),
+    /// Any other method, by name.
+    Custom(&'a str),
 }
 
 /// Type representing a parsed HTTP response.
@@ -144,42 +338,536 @@ pub struct Response<'a> {
     pub content_type: Option<ContentType>,
     /// The HTTP response body.
     pub payload: Option<&'a [u8]>,
+    /// The raw HTTP response header, from the status line to the terminating empty line.
+    pub(crate) raw_headers: &'a str,
+}
+
+impl<'a> Response<'a> {
+    /// Return the response header exactly as received, from the status line to the terminating
+    /// empty line, for forwarding it as is.
+    ///
+    /// Header lookups compare names case-insensitively, but the header is kept unmodified, with
+    /// the original casing of names and values.
+    pub fn raw_headers(&self) -> &'a str {
+        self.raw_headers
+    }
+
+    /// Return the status line exactly as received, without its line break, such as
+    /// `HTTP/1.1 200 OK`.
+    pub fn status_line(&self) -> &'a str {
+        self.raw_headers.split("\r\n").next().unwrap_or("")
+    }
+
+    /// Return the numeric status code of the response, including codes without a [`Status`]
+    /// variant of their own, such as 429.
+    pub fn status_code(&self) -> u16 {
+        self.status.code()
+    }
+
+    /// Return the reason phrase of the status line, such as `Not Found`, or an empty string if the
+    /// server sent none.
+    ///
+    /// The phrase is informational only: the [`status`](Self::status) is what tells the outcome.
+    pub fn reason(&self) -> &'a str {
+        parse_status_line(self.status_line()).map_or("", |(_, reason)| reason)
+    }
+
+    /// Iterate over the name and value of each response header, in the order received.
+    ///
+    /// Names and values are trimmed of surrounding whitespace, and reference the buffer holding
+    /// the response.
+    pub fn headers(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.raw_headers
+            .split("\r\n")
+            .skip(1)
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+    }
+
+    /// Return the value of the first header named `name`, compared case-insensitively.
+    ///
+    /// Use [`headers`](Self::headers) to get every value of a header that is repeated.
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    /// Copy the value of the first header named `name` into `dst`, so that it outlives the buffer
+    /// holding the response.
+    ///
+    /// Returns the copied value, or `None` if there is no such header. Fails with
+    /// [`Error::BufferTooSmall`] if the value does not fit in `dst`.
+    pub fn header_into<'d>(&self, name: &str, dst: &'d mut [u8]) -> Result<Option<&'d str>, Error> {
+        match self.header(name) {
+            Some(value) if value.len() <= dst.len() => {
+                let dst = &mut dst[..value.len()];
+                dst.copy_from_slice(value.as_bytes());
+                Ok(Some(core::str::from_utf8(dst)?))
+            }
+            Some(_) => Err(Error::BufferTooSmall),
+            None => Ok(None),
+        }
+    }
+
+    /// Reinterpret the body as a `T`, without copying it.
+    ///
+    /// Fails with [`Error::Codec`] if the body is not exactly `size_of::<T>()` bytes long, or is not
+    /// suitably aligned for `T`. As the body follows the header in `rx_buf`, its alignment is not
+    /// known in advance, so `T` should have an alignment of 1.
+    #[cfg(feature = "zerocopy")]
+    pub fn payload_as<T: zerocopy::FromBytes>(&self) -> Result<&'a T, Error> {
+        let payload = self.payload.unwrap_or(&[]);
+        match zerocopy::LayoutVerified::<_, T>::new(payload) {
+            Some(value) => Ok(value.into_ref()),
+            None => {
+                warn!(
+                    "Body of {} bytes does not hold a value of {} bytes",
+                    payload.len(),
+                    core::mem::size_of::<T>()
+                );
+                Err(Error::Codec)
+            }
+        }
+    }
+
+    /// Return the response if its status is successful, or fail with [`Error::Status`] otherwise.
+    pub fn error_for_status(self) -> Result<Self, Error> {
+        if self.status.is_success() {
+            Ok(self)
+        } else {
+            Err(Error::Status(self.status))
+        }
+    }
+
+    /// Check that the response has no body, failing with [`Error::UnexpectedBody`] otherwise.
+    ///
+    /// Meant for requests whose successful response is empty, for which a body signals that
+    /// something went wrong. Combine with [`error_for_status`](Self::error_for_status) to also
+    /// check the status.
+    pub fn expect_empty(&self) -> Result<(), Error> {
+        match self.payload {
+            Some(payload) if !payload.is_empty() => {
+                warn!("Expected an empty response, got {} bytes of body", payload.len());
+                Err(Error::UnexpectedBody)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the response redirects to another location.
+    pub fn is_redirect(&self) -> bool {
+        self.redirect_location().is_some()
+    }
+
+    /// Return the `Location` to follow if the response is a redirect.
+    pub fn redirect_location(&self) -> Option<&'a str> {
+        match self.status {
+            Status::MovedPermanently
+            | Status::Found
+            | Status::SeeOther
+            | Status::TemporaryRedirect
+            | Status::PermanentRedirect => self.header("location"),
+            _ => None,
+        }
+    }
+
+    /// Iterate over the names of the request headers the response varies on, listed in the `Vary`
+    /// headers.
+    ///
+    /// A response varying on `*` depends on more than request headers.
+    pub fn vary(&self) -> impl Iterator<Item = &'a str> {
+        self.headers()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("vary"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Parse the parameters of the `Keep-Alive` header, if present.
+    pub fn keep_alive(&self) -> Option<KeepAlive> {
+        self.header("keep-alive").map(KeepAlive::parse)
+    }
+
+    /// Return the range of the resource held by the response, from its `Content-Range` header.
+    ///
+    /// Returns `None` if the header is missing, invalid, or in a unit other than bytes.
+    pub fn content_range(&self) -> Option<ContentRange> {
+        self.header("content-range").and_then(ContentRange::parse)
+    }
+
+    /// Return the boundary separating the parts of a multipart body, from its `Content-Type`.
+    pub fn multipart_boundary(&self) -> Option<&'a str> {
+        if !match_prefix(self.header("content-type")?.trim_start(), "multipart/") {
+            return None;
+        }
+        self.content_type_param("boundary")
+    }
+
+    /// Return the character set of the body, from the `charset` parameter of its `Content-Type`,
+    /// such as `utf-8`.
+    pub fn charset(&self) -> Option<&'a str> {
+        self.content_type_param("charset")
+    }
+
+    /// Return the value of the parameter `name` of the `Content-Type` header, without its quotes.
+    fn content_type_param(&self, name: &str) -> Option<&'a str> {
+        let (_, params) = self.header("content-type")?.split_once(';')?;
+        params.split(';').find_map(|param| {
+            let (param, value) = param.split_once('=')?;
+            if param.trim().eq_ignore_ascii_case(name) {
+                let value = value.trim();
+                Some(
+                    value
+                        .strip_prefix('"')
+                        .and_then(|v| v.strip_suffix('"'))
+                        .unwrap_or(value),
+                )
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Classify the body by its `Content-Type`, including structured syntax suffixes such as
+    /// `application/problem+json`.
+    ///
+    /// A response without a content type is considered binary.
+    pub fn body_kind(&self) -> BodyKind {
+        self.header("content-type").map_or(BodyKind::Binary, BodyKind::classify)
+    }
+
+    /// Parse the `Strict-Transport-Security` header, if present and valid.
+    pub fn hsts(&self) -> Option<Hsts> {
+        self.header("strict-transport-security").and_then(Hsts::parse)
+    }
+
+    /// Iterate over the methods listed in the `Allow` headers, as sent in answer to `OPTIONS`
+    /// requests or with `405 Method Not Allowed`.
+    pub fn allowed_methods(&self) -> impl Iterator<Item = AllowedMethod<'a>> {
+        self.headers()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("allow"))
+            .flat_map(|(_, value)| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Method::parse(name).map_or(AllowedMethod::Custom(name), AllowedMethod::Known))
+    }
+
+    /// Parse the `Bearer` challenge of the `WWW-Authenticate` headers, if present.
+    pub fn bearer_challenge(&self) -> Option<BearerChallenge<'a>> {
+        self.headers()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("www-authenticate"))
+            .find_map(|(_, value)| BearerChallenge::parse(value))
+    }
+}
+
+/// The parameters of a `Keep-Alive` header, as sent by servers keeping the connection open.
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct KeepAlive {
+    /// The number of seconds an idle connection is kept open.
+    pub timeout: Option<u32>,
+    /// The number of further requests allowed on the connection.
+    pub max: Option<u32>,
+}
+
+impl KeepAlive {
+    fn parse(value: &str) -> KeepAlive {
+        let mut keep_alive = KeepAlive::default();
+        for param in value.split(',') {
+            if let Some((name, value)) = param.split_once('=') {
+                let name = name.trim();
+                let value = value.trim().parse().ok();
+                if name.eq_ignore_ascii_case("timeout") {
+                    keep_alive.timeout = value;
+                } else if name.eq_ignore_ascii_case("max") {
+                    keep_alive.max = value;
+                }
+            }
+        }
+        keep_alive
+    }
+}
+
+/// The directives of a `Strict-Transport-Security` header, asking for the host to only be
+/// accessed over TLS.
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Hsts {
+    /// The number of seconds the host must only be accessed over TLS. Zero asks to forget the
+    /// policy.
+    pub max_age: u64,
+    /// Whether the policy also applies to subdomains of the host.
+    pub include_subdomains: bool,
+    /// Whether the host asks to be included in browser preload lists.
+    pub preload: bool,
+}
+
+impl Hsts {
+    fn parse(value: &str) -> Option<Hsts> {
+        let mut max_age = None;
+        let mut hsts = Hsts::default();
+        for directive in value.split(';') {
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("max-age") {
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                max_age = value.parse().ok();
+            } else if name.eq_ignore_ascii_case("includesubdomains") {
+                hsts.include_subdomains = true;
+            } else if name.eq_ignore_ascii_case("preload") {
+                hsts.preload = true;
+            }
+        }
+        // The max-age directive is required
+        hsts.max_age = max_age?;
+        Some(hsts)
+    }
+}
+
+/// The parameters of a `Bearer` challenge in a `WWW-Authenticate` header, as sent by servers
+/// rejecting a missing or invalid access token.
+///
+/// Quoted values are returned without their quotes, but escaped characters are left as is.
+#[derive(Debug, Default, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BearerChallenge<'a> {
+    /// The protection space of the resource.
+    pub realm: Option<&'a str>,
+    /// The scope required to access the resource.
+    pub scope: Option<&'a str>,
+    /// The error code, such as `invalid_token` or `insufficient_scope`.
+    pub error: Option<&'a str>,
+    /// A human-readable description of the error.
+    pub error_description: Option<&'a str>,
+}
+
+impl<'a> BearerChallenge<'a> {
+    /// Parse the first `Bearer` challenge of a `WWW-Authenticate` value, which may hold several
+    /// challenges separated by commas.
+    fn parse(value: &'a str) -> Option<BearerChallenge<'a>> {
+        let mut challenge = None;
+        let mut params = value;
+        loop {
+            params = params.trim_start_matches(|c: char| c == ',' || c.is_ascii_whitespace());
+            if params.is_empty() {
+                return challenge;
+            }
+            let end = params
+                .find(|c: char| c == ',' || c == '=' || c.is_ascii_whitespace())
+                .unwrap_or(params.len());
+            let (name, rest) = params.split_at(end);
+            let rest = match rest.trim_start().strip_prefix('=') {
+                Some(rest) => rest.trim_start(),
+                None => {
+                    // A token not followed by `=` is the scheme of the next challenge
+                    if challenge.is_some() {
+                        return challenge;
+                    }
+                    if name.eq_ignore_ascii_case("bearer") {
+                        challenge.replace(BearerChallenge::default());
+                    }
+                    params = rest;
+                    continue;
+                }
+            };
+            let value = if let Some(quoted) = rest.strip_prefix('"') {
+                // Find the closing quote, skipping escaped characters
+                let mut escaped = false;
+                let end = quoted
+                    .char_indices()
+                    .find(|(_, c)| {
+                        let end = *c == '"' && !escaped;
+                        escaped = *c == '\\' && !escaped;
+                        end
+                    })
+                    .map_or(quoted.len(), |(i, _)| i);
+                params = quoted.get(end + 1..).unwrap_or("");
+                &quoted[..end]
+            } else {
+                let end = rest.find(',').unwrap_or(rest.len());
+                params = &rest[end..];
+                rest[..end].trim_end()
+            };
+            // Parameters of other challenges are skipped
+            let challenge = match challenge.as_mut() {
+                Some(challenge) => challenge,
+                None => continue,
+            };
+            if name.eq_ignore_ascii_case("realm") {
+                challenge.realm.replace(value);
+            } else if name.eq_ignore_ascii_case("scope") {
+                challenge.scope.replace(value);
+            } else if name.eq_ignore_ascii_case("error") {
+                challenge.error.replace(value);
+            } else if name.eq_ignore_ascii_case("error_description") {
+                challenge.error_description.replace(value);
+            }
+        }
+    }
+}
+
+/// The range of a resource held by a partial response or by a part of a `multipart/byteranges`
+/// body, from its `Content-Range` header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ContentRange {
+    /// A range of bytes, as in `bytes 200-1023/1024`.
+    Bytes {
+        /// The offset of the first byte of the range.
+        start: u64,
+        /// The offset of the last byte of the range, inclusive.
+        end: u64,
+        /// The length of the complete resource, `None` if unknown as in `bytes 200-1023/*`.
+        total: Option<u64>,
+    },
+    /// No range, as in `bytes */1024`, sent with `416 Range Not Satisfiable` to give the length
+    /// of the complete resource.
+    Unsatisfied {
+        /// The length of the complete resource.
+        total: u64,
+    },
+}
+
+impl ContentRange {
+    /// Parse a `Content-Range` value, which is `None` if invalid or in a unit other than bytes.
+    pub(crate) fn parse(value: &str) -> Option<ContentRange> {
+        let (unit, range) = value.trim().split_once(' ')?;
+        if !unit.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let (range, total) = range.split_once('/')?;
+        let (range, total) = (range.trim(), total.trim());
+        if range == "*" {
+            return Some(ContentRange::Unsatisfied {
+                total: total.parse().ok()?,
+            });
+        }
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+        let total = match total {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        if end < start || total.map_or(false, |total| end >= total) {
+            return None;
+        }
+        Some(ContentRange::Bytes { start, end, total })
+    }
 }
 
 /// HTTP status types
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Status {
-    Ok = 200,
-    Created = 201,
-    Accepted = 202,
-    BadRequest = 400,
-    Unauthorized = 401,
-    Forbidden = 403,
-    NotFound = 404,
-    Unknown = 0,
+    Continue,
+    SwitchingProtocols,
+    EarlyHints,
+    Ok,
+    Created,
+    Accepted,
+    NoContent,
+    PartialContent,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    PreconditionFailed,
+    ExpectationFailed,
+    InternalServerError,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    /// A status code without a variant of its own.
+    Unknown(u16),
 }
 
 impl From<u32> for Status {
     fn from(from: u32) -> Status {
         match from {
+            100 => Status::Continue,
+            101 => Status::SwitchingProtocols,
+            103 => Status::EarlyHints,
             200 => Status::Ok,
             201 => Status::Created,
             202 => Status::Accepted,
+            204 => Status::NoContent,
+            206 => Status::PartialContent,
+            301 => Status::MovedPermanently,
+            302 => Status::Found,
+            303 => Status::SeeOther,
+            304 => Status::NotModified,
+            307 => Status::TemporaryRedirect,
+            308 => Status::PermanentRedirect,
             400 => Status::BadRequest,
             401 => Status::Unauthorized,
             403 => Status::Forbidden,
             404 => Status::NotFound,
+            412 => Status::PreconditionFailed,
+            417 => Status::ExpectationFailed,
+            500 => Status::InternalServerError,
+            502 => Status::BadGateway,
+            503 => Status::ServiceUnavailable,
+            504 => Status::GatewayTimeout,
             n => {
                 warn!("Unknown status code: {:?}", n);
-                Status::Unknown
+                Status::Unknown(u16::try_from(n).unwrap_or(0))
             }
         }
     }
 }
 
+impl Status {
+    /// Return the numeric status code, such as 404 for [`Status::NotFound`].
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Continue => 100,
+            Status::SwitchingProtocols => 101,
+            Status::EarlyHints => 103,
+            Status::Ok => 200,
+            Status::Created => 201,
+            Status::Accepted => 202,
+            Status::NoContent => 204,
+            Status::PartialContent => 206,
+            Status::MovedPermanently => 301,
+            Status::Found => 302,
+            Status::SeeOther => 303,
+            Status::NotModified => 304,
+            Status::TemporaryRedirect => 307,
+            Status::PermanentRedirect => 308,
+            Status::BadRequest => 400,
+            Status::Unauthorized => 401,
+            Status::Forbidden => 403,
+            Status::NotFound => 404,
+            Status::PreconditionFailed => 412,
+            Status::ExpectationFailed => 417,
+            Status::InternalServerError => 500,
+            Status::BadGateway => 502,
+            Status::ServiceUnavailable => 503,
+            Status::GatewayTimeout => 504,
+            Status::Unknown(code) => *code,
+        }
+    }
+
+    /// Whether the status is in the 2xx success class.
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+}
+
 /// HTTP content types
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum ContentType {
     ApplicationJson,
@@ -206,3 +894,429 @@ impl ContentType {
         }
     }
 }
+
+/// The kind of data a response body holds, as announced by its content type.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum BodyKind {
+    /// JSON data.
+    Json,
+    /// Text, including XML.
+    Text,
+    /// Any other data.
+    Binary,
+}
+
+impl BodyKind {
+    fn classify(content_type: &str) -> BodyKind {
+        let media_type = content_type.split(';').next().unwrap_or("").trim();
+        let (kind, subtype) = media_type.split_once('/').unwrap_or((media_type, ""));
+        let suffix = subtype.rsplit_once('+').map(|(_, suffix)| suffix);
+        if subtype.eq_ignore_ascii_case("json") || suffix.filter(|s| s.eq_ignore_ascii_case("json")).is_some() {
+            BodyKind::Json
+        } else if kind.eq_ignore_ascii_case("text")
+            || subtype.eq_ignore_ascii_case("xml")
+            || suffix.filter(|s| s.eq_ignore_ascii_case("xml")).is_some()
+        {
+            BodyKind::Text
+        } else {
+            BodyKind::Binary
+        }
+    }
+}
+
+/// Split a status line such as `HTTP/1.1 404 Not Found` into its status code and reason phrase.
+///
+/// The version, code and reason are separated by runs of whitespace. Returns `None` unless the
+/// code is three digits in the range 100 to 599.
+pub(crate) fn parse_status_line(line: &str) -> Option<(u32, &str)> {
+    let (_version, rest) = line.split_once(|c: char| c.is_ascii_whitespace())?;
+    let rest = rest.trim_start();
+    let (code, reason) = rest.split_once(|c: char| c.is_ascii_whitespace()).unwrap_or((rest, ""));
+    if code.len() != 3 || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code = code.parse().ok().filter(|code| (100..600).contains(code))?;
+    Some((code, reason.trim()))
+}
+
+fn match_prefix(value: &str, prefix: &str) -> bool {
+    value
+        .get(..prefix.len())
+        .filter(|p| p.eq_ignore_ascii_case(prefix))
+        .is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_alive() {
+        assert_eq!(
+            KeepAlive {
+                timeout: Some(5),
+                max: Some(100)
+            },
+            KeepAlive::parse("timeout=5, max=100")
+        );
+        assert_eq!(
+            KeepAlive {
+                timeout: None,
+                max: Some(3)
+            },
+            KeepAlive::parse("Max=3")
+        );
+        assert_eq!(KeepAlive::default(), KeepAlive::parse("timeout=soon"));
+    }
+
+    #[test]
+    fn test_response_keep_alive() {
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.0 200 OK\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5\r\n\r\n",
+        };
+        assert_eq!(Some(5), response.keep_alive().unwrap().timeout);
+    }
+
+    #[test]
+    fn test_redirect_location() {
+        let mut response = Response {
+            status: Status::Found,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 302 Found\r\nLocation: /elsewhere\r\n\r\n",
+        };
+        assert!(response.is_redirect());
+        assert_eq!(Some("/elsewhere"), response.redirect_location());
+
+        response.status = Status::Ok;
+        assert!(!response.is_redirect());
+        assert_eq!(None, response.redirect_location());
+
+        response.status = Status::MovedPermanently;
+        response.raw_headers = "HTTP/1.1 301 Moved Permanently\r\n\r\n";
+        assert!(!response.is_redirect());
+    }
+
+    #[test]
+    fn test_headers() {
+        let response = Response {
+            status: Status::ServiceUnavailable,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 503 Service Unavailable\r\nETag: \"v1\"\r\nRetry-After:120\r\nX-RateLimit-Remaining:  0 \r\n\r\n",
+        };
+        assert_eq!(Some("\"v1\""), response.header("etag"));
+        assert_eq!(Some("120"), response.header("Retry-After"));
+        assert_eq!(Some("0"), response.header("x-ratelimit-remaining"));
+        assert_eq!(None, response.header("location"));
+        assert_eq!(3, response.headers().count());
+    }
+
+    #[test]
+    fn test_allowed_methods() {
+        let response = Response {
+            status: Status::NoContent,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 204 No Content\r\nAllow: GET, HEAD,PUT\r\nallow: , delete\r\n\r\n",
+        };
+        let mut methods = response.allowed_methods();
+        assert_eq!(Some(AllowedMethod::Known(Method::GET)), methods.next());
+        assert_eq!(Some(AllowedMethod::Custom("HEAD")), methods.next());
+        assert_eq!(Some(AllowedMethod::Known(Method::PUT)), methods.next());
+        assert_eq!(Some(AllowedMethod::Custom("delete")), methods.next());
+        assert_eq!(None, methods.next());
+    }
+
+    #[test]
+    fn test_vary() {
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\nVary: Accept-Language, Accept-Encoding\r\nvary: Origin,\r\n\r\n",
+        };
+        let mut vary = response.vary();
+        assert_eq!(Some("Accept-Language"), vary.next());
+        assert_eq!(Some("Accept-Encoding"), vary.next());
+        assert_eq!(Some("Origin"), vary.next());
+        assert_eq!(None, vary.next());
+    }
+
+    #[test]
+    fn test_header_into() {
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\nETag: \"abc\"\r\n\r\n",
+        };
+        let mut dst = [0; 8];
+        assert_eq!(Some("\"abc\""), response.header_into("etag", &mut dst).unwrap());
+        assert_eq!(None, response.header_into("location", &mut dst).unwrap());
+        let mut dst = [0; 4];
+        assert!(matches!(
+            response.header_into("etag", &mut dst),
+            Err(Error::BufferTooSmall)
+        ));
+    }
+
+    #[test]
+    fn test_bearer_challenge() {
+        assert_eq!(
+            Some(BearerChallenge {
+                realm: Some("example"),
+                scope: None,
+                error: Some("invalid_token"),
+                error_description: Some(r#"The access token expired, \"renew\" it"#),
+            }),
+            BearerChallenge::parse(
+                r#"Bearer realm="example", error="invalid_token", error_description="The access token expired, \"renew\" it""#
+            )
+        );
+        assert_eq!(
+            Some(BearerChallenge {
+                error: Some("insufficient_scope"),
+                scope: Some("read write"),
+                ..Default::default()
+            }),
+            BearerChallenge::parse("bearer error=insufficient_scope , scope=\"read write\"")
+        );
+        assert_eq!(Some(BearerChallenge::default()), BearerChallenge::parse("Bearer"));
+        assert_eq!(None, BearerChallenge::parse("Basic realm=\"example\""));
+
+        // Several challenges in one header, with a token68 and a quoted comma in the others
+        let expected = Some(BearerChallenge {
+            realm: Some("api"),
+            scope: Some("read"),
+            ..Default::default()
+        });
+        assert_eq!(
+            expected,
+            BearerChallenge::parse(r#"Basic realm="a, b", Bearer realm=api, scope=read, Newauth abc=="#)
+        );
+        assert_eq!(
+            expected,
+            BearerChallenge::parse(r#"Negotiate abc==, Bearer realm="api",scope=read"#)
+        );
+        assert_eq!(None, BearerChallenge::parse("Basic realm=x, Newauth realm=bearer"));
+
+        let response = Response {
+            status: Status::Unauthorized,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=x\r\nWWW-Authenticate: Bearer error=\"invalid_token\"\r\n\r\n",
+        };
+        assert_eq!(Some("invalid_token"), response.bearer_challenge().unwrap().error);
+    }
+
+    #[test]
+    fn test_body_kind() {
+        assert_eq!(BodyKind::Json, BodyKind::classify("application/json"));
+        assert_eq!(
+            BodyKind::Json,
+            BodyKind::classify("Application/Problem+JSON; charset=utf-8")
+        );
+        assert_eq!(BodyKind::Text, BodyKind::classify("text/plain;charset=utf-8"));
+        assert_eq!(BodyKind::Text, BodyKind::classify("application/atom+xml"));
+        assert_eq!(BodyKind::Text, BodyKind::classify("application/xml"));
+        assert_eq!(BodyKind::Binary, BodyKind::classify("application/octet-stream"));
+        assert_eq!(BodyKind::Binary, BodyKind::classify("image/png"));
+    }
+
+    #[test]
+    fn test_error_for_status() {
+        let mut response = Response {
+            status: Status::NoContent,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 204 No Content\r\n\r\n",
+        };
+        response = response.error_for_status().unwrap();
+        response.status = Status::NotFound;
+        assert!(matches!(
+            response.error_for_status(),
+            Err(Error::Status(Status::NotFound))
+        ));
+    }
+
+    #[test]
+    fn test_expect_empty() {
+        let mut response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+        };
+        assert!(response.expect_empty().is_ok());
+        response.payload = Some(b"error");
+        assert!(matches!(response.expect_empty(), Err(Error::UnexpectedBody)));
+    }
+
+    #[cfg(feature = "zerocopy")]
+    #[test]
+    fn test_payload_as() {
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: Some(b"\x01\x02\x03\x04"),
+            raw_headers: "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\n",
+        };
+        assert_eq!(&[1, 2, 3, 4], response.payload_as::<[u8; 4]>().unwrap());
+        assert!(matches!(response.payload_as::<[u8; 2]>(), Err(Error::Codec)));
+    }
+
+    #[test]
+    fn test_multipart_boundary() {
+        let mut response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers:
+                "HTTP/1.1 206 Partial Content\r\nContent-Type: multipart/byteranges; boundary=3d6b6a416f9b5\r\n\r\n",
+        };
+        assert_eq!(Some("3d6b6a416f9b5"), response.multipart_boundary());
+        response.raw_headers =
+            "HTTP/1.1 200 OK\r\nContent-Type: Multipart/Mixed; charset=utf-8; boundary=\"a b\"\r\n\r\n";
+        assert_eq!(Some("a b"), response.multipart_boundary());
+        response.raw_headers = "HTTP/1.1 200 OK\r\nContent-Type: text/plain; boundary=x\r\n\r\n";
+        assert_eq!(None, response.multipart_boundary());
+    }
+
+    #[test]
+    fn test_content_range() {
+        let mut response = Response {
+            status: Status::PartialContent,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 200-1023/1024\r\n\r\n",
+        };
+        assert_eq!(
+            Some(ContentRange::Bytes {
+                start: 200,
+                end: 1023,
+                total: Some(1024)
+            }),
+            response.content_range()
+        );
+        response.raw_headers = "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-5000000000/*\r\n\r\n";
+        assert_eq!(
+            Some(ContentRange::Bytes {
+                start: 0,
+                end: 5_000_000_000,
+                total: None
+            }),
+            response.content_range()
+        );
+        response.raw_headers = "HTTP/1.1 416 Range Not Satisfiable\r\nContent-Range: bytes */1024\r\n\r\n";
+        assert_eq!(
+            Some(ContentRange::Unsatisfied { total: 1024 }),
+            response.content_range()
+        );
+        for invalid in [
+            "bytes 10-5/20",
+            "bytes 0-20/20",
+            "bytes */*",
+            "items 0-1/2",
+            "bytes 0-/2",
+        ] {
+            assert_eq!(None, ContentRange::parse(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_status_line() {
+        let response = Response {
+            status: Status::Ok,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 200 Okay then\r\nContent-Length: 0\r\n\r\n",
+        };
+        assert_eq!("HTTP/1.1 200 Okay then", response.status_line());
+        assert_eq!("Okay then", response.reason());
+    }
+
+    #[test]
+    fn test_charset() {
+        let mut response = Response {
+            status: Status::Ok,
+            content_type: Some(ContentType::ApplicationJson),
+            payload: None,
+            raw_headers: "HTTP/1.1 200 OK\r\nContent-Type: application/json; charset=utf-8\r\n\r\n",
+        };
+        assert_eq!(Some("utf-8"), response.charset());
+
+        response.raw_headers =
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain;format=flowed ; CharSet = \"ISO-8859-1\" \r\n\r\n";
+        assert_eq!(Some("ISO-8859-1"), response.charset());
+        response.raw_headers = "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\n";
+        assert_eq!(None, response.charset());
+        response.raw_headers =
+            "HTTP/1.1 200 OK\r\nContent-Type: multipart/mixed; boundary=\"x\"; charset=utf-8\r\n\r\n";
+        assert_eq!(Some("x"), response.multipart_boundary());
+        assert_eq!(Some("utf-8"), response.charset());
+    }
+
+    #[test]
+    fn test_status_code() {
+        assert_eq!(Status::NotFound, Status::from(404));
+        assert_eq!(404, Status::NotFound.code());
+        assert_eq!(Status::Unknown(429), Status::from(429));
+        assert_eq!(429, Status::from(429).code());
+        assert!(Status::from(299).is_success());
+        assert!(!Status::from(418).is_success());
+
+        let response = Response {
+            status: Status::from(418),
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 418 I'm a teapot\r\n\r\n",
+        };
+        assert_eq!(418, response.status_code());
+    }
+
+    #[test]
+    fn test_parse_status_line() {
+        assert_eq!(Some((404, "Not Found")), parse_status_line("HTTP/1.0 404 Not Found"));
+        assert_eq!(Some((200, "OK")), parse_status_line("HTTP/1.1  \t200   OK "));
+        assert_eq!(Some((204, "")), parse_status_line("HTTP/1.1 204"));
+        for invalid in [
+            "HTTP/1.1",
+            "HTTP/1.1 ",
+            "HTTP/1.1 20 OK",
+            "HTTP/1.1 2000 OK",
+            "HTTP/1.1 +20 OK",
+            "HTTP/1.1 abc OK",
+            "HTTP/1.1 099 OK",
+            "HTTP/1.1 600 OK",
+        ] {
+            assert_eq!(None, parse_status_line(invalid), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_hsts() {
+        assert_eq!(
+            Some(Hsts {
+                max_age: 31536000,
+                include_subdomains: true,
+                preload: false,
+            }),
+            Hsts::parse("max-age=31536000; includeSubDomains")
+        );
+        assert_eq!(
+            Some(Hsts {
+                max_age: 60,
+                include_subdomains: false,
+                preload: true,
+            }),
+            Hsts::parse("Preload; Max-Age=\"60\"")
+        );
+        assert_eq!(None, Hsts::parse("includeSubDomains"));
+        assert_eq!(None, Hsts::parse("max-age=forever"));
+    }
+}
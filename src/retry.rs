@@ -0,0 +1,53 @@
+//! Deciding whether a failed request is retried by [`HttpClient::request_with_retry`].
+//!
+//! [`HttpClient::request_with_retry`]: crate::client::HttpClient::request_with_retry
+use crate::client::Error;
+use crate::request::{Method, Status};
+
+/// The outcome of an attempt at a request, passed to the retry predicate.
+#[derive(Debug)]
+pub struct RetryContext<'a> {
+    /// The method of the request.
+    pub method: Method,
+    /// The number of attempts made so far, starting at 1.
+    pub attempt: u32,
+    /// The status of the response, or the error that failed the attempt.
+    pub result: Result<Status, &'a Error>,
+    /// The time elapsed since the first attempt started, in seconds.
+    pub elapsed: u64,
+}
+
+/// Retry idempotent requests up to 3 attempts, when they failed with a network error or the server
+/// was temporarily unable to handle them.
+pub fn retry_idempotent(context: &RetryContext<'_>) -> bool {
+    let idempotent = matches!(context.method, Method::GET | Method::PUT | Method::DELETE);
+    let transient = matches!(
+        context.result,
+        Err(Error::Network(_)) | Ok(Status::ServiceUnavailable) | Ok(Status::GatewayTimeout)
+    );
+    idempotent && transient && context.attempt < 3
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_idempotent() {
+        let mut context = RetryContext {
+            method: Method::GET,
+            attempt: 1,
+            result: Ok(Status::ServiceUnavailable),
+            elapsed: 0,
+        };
+        assert!(retry_idempotent(&context));
+        context.attempt = 3;
+        assert!(!retry_idempotent(&context));
+        context.attempt = 1;
+        context.result = Ok(Status::NotFound);
+        assert!(!retry_idempotent(&context));
+        context.result = Ok(Status::ServiceUnavailable);
+        context.method = Method::POST;
+        assert!(!retry_idempotent(&context));
+    }
+}
@@ -0,0 +1,131 @@
+//! Validation of the server's answer to a WebSocket upgrade request.
+//!
+//! An upgrade is a GET request carrying `Upgrade: websocket`, `Connection: Upgrade`,
+//! `Sec-WebSocket-Version: 13` and a `Sec-WebSocket-Key` header holding a random 16 byte nonce
+//! in base64. The server proves it understood the request by answering with the
+//! [accept key](accept_key) derived from the nonce. Send the request with
+//! [`HttpClient::request_streaming`](crate::client::HttpClient::request_streaming) so that the
+//! connection can be used for WebSocket frames once [`validate_upgrade`] accepts the response.
+use crate::client::Error;
+use crate::request::{Response, Status};
+use sha1::{Digest, Sha1};
+
+/// The GUID appended to the key by both sides to compute the accept key, from RFC 6455.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// The length of an accept key, the base64 encoding of a 20 byte SHA-1 digest.
+pub const ACCEPT_KEY_LEN: usize = 28;
+
+/// Compute the `Sec-WebSocket-Accept` value a server must answer for `key` into `dst`.
+pub fn accept_key<'d>(key: &str, dst: &'d mut [u8; ACCEPT_KEY_LEN]) -> &'d str {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    let digest = sha1.finalize();
+    let n = base64::encode_config_slice(&digest[..], base64::STANDARD, &mut dst[..]);
+    // Base64 output is ASCII
+    core::str::from_utf8(&dst[..n]).unwrap_or("")
+}
+
+/// Check that `response` accepts the upgrade requested with `Sec-WebSocket-Key: <key>`.
+///
+/// The response must switch protocols to `websocket`, and its `Sec-WebSocket-Accept` header must
+/// match the [accept key](accept_key) of `key` exactly. Fails with
+/// [`Error::WebSocketHandshakeFailed`] otherwise, in which case the connection must not be used
+/// for WebSocket frames.
+pub fn validate_upgrade(response: &Response<'_>, key: &str) -> Result<(), Error> {
+    if response.status != Status::SwitchingProtocols {
+        warn!("WebSocket upgrade answered with {:?}", response.status);
+        return Err(Error::WebSocketHandshakeFailed);
+    }
+    let upgrade = response.header("upgrade").unwrap_or("");
+    let connection = response.header("connection").unwrap_or("");
+    if !upgrade.eq_ignore_ascii_case("websocket")
+        || !connection
+            .split(',')
+            .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    {
+        warn!("WebSocket upgrade switched to another protocol");
+        return Err(Error::WebSocketHandshakeFailed);
+    }
+
+    let mut expected = [0; ACCEPT_KEY_LEN];
+    if response.header("sec-websocket-accept") != Some(accept_key(key, &mut expected)) {
+        warn!("WebSocket upgrade answered with a wrong accept key");
+        return Err(Error::WebSocketHandshakeFailed);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::HttpClient;
+    use crate::mock::{block_on, MockNetwork};
+    use crate::request::Request;
+    use embedded_io::asynch::Read;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    #[test]
+    fn test_accept_key() {
+        // The example of RFC 6455, section 1.3
+        let mut dst = [0; ACCEPT_KEY_LEN];
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key(KEY, &mut dst));
+    }
+
+    #[test]
+    fn test_validate_upgrade() {
+        let rx = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n\x81\x02hi";
+        let mut tx = [0; 256];
+        let mut conn = MockNetwork::new(rx, &mut tx);
+        let mut client = HttpClient::new(&mut conn, "localhost");
+        let headers = [
+            ("Upgrade", "websocket"),
+            ("Connection", "Upgrade"),
+            ("Sec-WebSocket-Key", KEY),
+            ("Sec-WebSocket-Version", "13"),
+        ];
+        let request = Request::get().path("/chat").headers(&headers).build();
+        let mut rx_buf = [0; 256];
+        let (response, mut frames) = block_on(client.request_streaming(request, &mut rx_buf)).unwrap();
+        assert!(validate_upgrade(&response, KEY).is_ok());
+
+        let mut frame = [0; 4];
+        assert_eq!(4, block_on(frames.read(&mut frame)).unwrap());
+        assert_eq!(b"\x81\x02hi", &frame);
+    }
+
+    #[test]
+    fn test_reject_wrong_accept_key() {
+        let mut response = Response {
+            status: Status::SwitchingProtocols,
+            content_type: None,
+            payload: None,
+            raw_headers: "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo\r\n\r\n",
+        };
+        assert!(matches!(
+            validate_upgrade(&response, KEY),
+            Err(Error::WebSocketHandshakeFailed)
+        ));
+
+        response.raw_headers = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: S3PPLMBITXAQ9KYGZZHZRBK+XOO=\r\n\r\n";
+        assert!(matches!(
+            validate_upgrade(&response, KEY),
+            Err(Error::WebSocketHandshakeFailed)
+        ));
+
+        response.raw_headers = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\r\n";
+        assert!(matches!(
+            validate_upgrade(&response, KEY),
+            Err(Error::WebSocketHandshakeFailed)
+        ));
+
+        response.status = Status::Ok;
+        response.raw_headers = "HTTP/1.1 200 OK\r\nSec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert!(matches!(
+            validate_upgrade(&response, KEY),
+            Err(Error::WebSocketHandshakeFailed)
+        ));
+    }
+}